@@ -1,36 +1,93 @@
-use std::{error::Error, path::PathBuf};
+use std::{error::Error, io, path::PathBuf};
 
-use clap::{Args, Parser, ValueEnum};
-use renamer_lib::{ActionGroup, RenamePattern, patterns::ActionOptions};
+use chrono::{DateTime, Local};
+use clap::{Args, Parser, Subcommand, ValueEnum, error::ErrorKind};
+use renamer_lib::{
+    ActionGroup, ConflictPolicy, RenamePattern, SortOrder, journal::Journal,
+    patterns::ActionOptions, report::Report,
+};
+use serde::Serialize;
 
 #[derive(Parser, Debug)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct RenamerArgs {
+    #[command(subcommand)]
+    journal: Option<JournalCommand>,
     #[command(flatten)]
     pattern_preset: PatternPresetArgs,
     #[arg(short, long, num_args = 1.., value_delimiter = ' ')]
     files: Vec<PathBuf>,
     #[arg(long = "preserve-extension")]
     dont_preserve_extension: bool,
-    #[arg(short, long)]
-    overwrite: bool,
+    /// What to do when a target already exists: skip, overwrite, fail,
+    /// append-counter[=FORMAT] or keep-both
+    #[arg(long, default_value_t = ConflictPolicy::Fail)]
+    on_conflict: ConflictPolicy,
+    /// Order the files are renamed and reported in: insertion, name, natural, mtime,
+    /// size or extension
+    #[arg(long, default_value_t = SortOrder::Insertion)]
+    sort: SortOrder,
+    /// Print the planned renames without performing them
+    #[arg(long)]
+    dry_run: bool,
+    /// Don't record the renames in the undo journal
+    #[arg(long)]
+    no_journal: bool,
+    /// Revert every rename if any of them fails
+    #[arg(long)]
+    atomic: bool,
+    /// How to print the results
+    #[arg(long, value_enum, default_value_t = Output::Text, global = true)]
+    output: Output,
 }
 impl RenamerArgs {
-    fn deconstruct(self) -> (PatternOrPreset, Vec<PathBuf>, ActionOptions) {
+    fn deconstruct(self) -> Result<Command, clap::Error> {
+        if let Some(command) = self.journal {
+            return match command {
+                JournalCommand::Undo { n } => Ok(Command::Undo(n)),
+                JournalCommand::UndoBatch { id } => Ok(Command::UndoBatch(id)),
+                JournalCommand::History => Ok(Command::History),
+            };
+        }
         let pat_or_preset = if let Some(preset) = self.pattern_preset.preset {
             PatternOrPreset::Preset(preset)
         } else if let Some(pattern) = self.pattern_preset.pattern {
             PatternOrPreset::Pattern(pattern)
+        } else if let Some(replace) = self.pattern_preset.replace {
+            PatternOrPreset::Replace(replace)
         } else {
-            unreachable!()
+            return Err(clap::Error::raw(
+                ErrorKind::MissingRequiredArgument,
+                "One of --pattern, --preset or --replace is required to rename files\n",
+            ));
         };
-        (
+        Ok(Command::Rename {
             pat_or_preset,
-            self.files,
-            ActionOptions::new(!self.dont_preserve_extension, self.overwrite),
-        )
+            files: self.files,
+            sort: self.sort,
+            options: ActionOptions::new(!self.dont_preserve_extension, self.on_conflict),
+            dry_run: self.dry_run,
+            journal: !self.no_journal,
+            atomic: self.atomic,
+        })
     }
 }
 
+enum Command {
+    Rename {
+        pat_or_preset: PatternOrPreset,
+        files: Vec<PathBuf>,
+        sort: SortOrder,
+        options: ActionOptions,
+        dry_run: bool,
+        journal: bool,
+        atomic: bool,
+    },
+    Undo(usize),
+    UndoBatch(u64),
+    History,
+}
+
 #[derive(Args, Debug)]
 #[group(required = true, multiple = false)]
 struct PatternPresetArgs {
@@ -38,6 +95,25 @@ struct PatternPresetArgs {
     pattern: Option<String>,
     #[arg(long)]
     preset: Option<Preset>,
+    /// Search and replace in the names, e.g. 's/_/ /g', with $1 or ${name} referring to
+    /// the regex's groups and the flags g (every match) and i (ignore case). A group name
+    /// runs as far as it can, '$1_' is the group named '1_', use '${1}_' for group 1
+    /// followed by '_'
+    #[arg(short, long)]
+    replace: Option<String>,
+}
+
+#[derive(Subcommand, Debug)]
+enum JournalCommand {
+    /// Undo the last N batches of renames
+    Undo {
+        #[arg(default_value_t = 1)]
+        n: usize,
+    },
+    /// Undo the batch with the given id
+    UndoBatch { id: u64 },
+    /// List the batches recorded in the undo journal
+    History,
 }
 
 #[derive(ValueEnum, Clone, Copy, Debug)]
@@ -54,33 +130,154 @@ impl Preset {
 enum PatternOrPreset {
     Pattern(String),
     Preset(Preset),
+    Replace(String),
 }
 impl PatternOrPreset {
     fn into_pattern(self, options: ActionOptions) -> Result<RenamePattern, Box<dyn Error>> {
         match self {
-            PatternOrPreset::Pattern(pat) => {
-                RenamePattern::parse(pat.as_str(), options).map_err(Into::into)
-            }
+            PatternOrPreset::Pattern(pat) => RenamePattern::parse(pat.as_str(), options),
             PatternOrPreset::Preset(preset) => Ok(preset.into_pattern(options)),
+            PatternOrPreset::Replace(replace) => Ok(RenamePattern::replace(&replace, options)?),
         }
     }
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
-    let (pat_or_preset, files, options) = RenamerArgs::parse().deconstruct();
+    let args = RenamerArgs::parse();
+    let output = args.output;
+    match args.deconstruct().unwrap_or_else(|e| e.exit()) {
+        Command::Rename {
+            pat_or_preset,
+            files,
+            sort,
+            options,
+            dry_run,
+            journal,
+            atomic,
+        } => {
+            let reports = rename(
+                pat_or_preset,
+                files,
+                sort,
+                options,
+                dry_run,
+                journal,
+                atomic,
+            )?;
+            print_reports(reports, output)
+        }
+        Command::Undo(n) => print_reports(Journal::open_default()?.undo_last(n)?, output),
+        Command::UndoBatch(id) => print_reports(Journal::open_default()?.undo_batch(id)?, output),
+        Command::History => {
+            let batches = Journal::open_default()?.batches()?;
+            let rows = batches.iter().rev().map(|batch| HistoryRow {
+                id: batch.id,
+                timestamp: batch.timestamp,
+                files: batch.entries.len(),
+            });
+            print_rows(rows, output, |row| {
+                format!(
+                    "{}: {} files renamed at {}",
+                    row.id,
+                    row.files,
+                    row.timestamp.format("%Y-%m-%d %H:%M:%S")
+                )
+            })
+        }
+    }
+}
+
+fn rename(
+    pat_or_preset: PatternOrPreset,
+    files: Vec<PathBuf>,
+    sort: SortOrder,
+    options: ActionOptions,
+    dry_run: bool,
+    journal: bool,
+    atomic: bool,
+) -> Result<Vec<Report>, Box<dyn Error>> {
     let pattern: RenamePattern = pat_or_preset.into_pattern(options)?;
     let mut action_group = ActionGroup::new(0);
+    action_group.set_sort_order(sort);
     for file in files.into_iter() {
         action_group.add_file(file.canonicalize()?);
     }
     action_group.add_pattern(pattern);
-    let reports = action_group.execute();
-    for report in reports {
-        match report {
-            Ok(rep) => println!("Success!: {rep:?}"),
-            Err(err) => println!("Failure!: {err}"),
+    let plan = action_group.plan();
+    if dry_run {
+        return Ok(plan.preview());
+    }
+    let reports = if atomic {
+        plan.execute_atomic()
+    } else {
+        plan.execute()
+    };
+    if journal {
+        // Printed to stderr to keep machine readable output on stdout clean.
+        match Journal::open_default().and_then(|j| j.record(reports.iter())) {
+            Ok(Some(id)) => eprintln!("Recorded as batch {id}, undo with `undo-batch {id}`"),
+            Ok(None) => (),
+            Err(e) => eprintln!("Could not record renames in the undo journal!: {e}"),
         }
     }
+    Ok(reports)
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Output {
+    Text,
+    /// A single JSON array
+    Json,
+    /// One JSON object per line
+    Jsonl,
+    Csv,
+}
+
+/// A batch in the undo journal, as listed by `history`.
+#[derive(Serialize)]
+struct HistoryRow {
+    id: u64,
+    timestamp: DateTime<Local>,
+    files: usize,
+}
+
+/// Machine readable formats share the schema of [`Report::record`]: from, to, status,
+/// error and overwrote.
+fn print_reports(reports: Vec<Report>, output: Output) -> Result<(), Box<dyn Error>> {
+    print_rows(reports, output, |report| {
+        format!("{report} [{:.2?}]", report.duration())
+    })
+}
 
+/// Prints `rows` in the `output` format, using `text` for plain text output.
+fn print_rows<T: Serialize>(
+    rows: impl IntoIterator<Item = T>,
+    output: Output,
+    text: impl Fn(&T) -> String,
+) -> Result<(), Box<dyn Error>> {
+    match output {
+        Output::Text => {
+            for row in rows {
+                println!("{}", text(&row));
+            }
+        }
+        Output::Json => {
+            let rows: Vec<T> = rows.into_iter().collect();
+            serde_json::to_writer_pretty(io::stdout().lock(), &rows)?;
+            println!();
+        }
+        Output::Jsonl => {
+            for row in rows {
+                println!("{}", serde_json::to_string(&row)?);
+            }
+        }
+        Output::Csv => {
+            let mut writer = csv::Writer::from_writer(io::stdout().lock());
+            for row in rows {
+                writer.serialize(row)?;
+            }
+            writer.flush()?;
+        }
+    }
     Ok(())
 }
@@ -16,6 +16,12 @@ fn remove_file(group_id: i32, file_id: i32, state: RenamerState) {
     log::trace!("Removing file callback triggered");
     state.write().remove_file_from_group(group_id, file_id);
 }
+fn set_sort_order(group_id: i32, order: &str, state: RenamerState) {
+    match order.parse() {
+        Ok(order) => state.write().set_sort_order(group_id, order),
+        Err(e) => log::error!("Error setting sort order!: {e}"),
+    }
+}
 fn add_action(group_id: i32, action: S_Action, state: RenamerState) {
     match action.try_into() {
         Ok(new_action) => state.write().add_pattern_to_group(group_id, new_action),
@@ -46,6 +52,21 @@ fn go_pressed(state: RenamerState) {
     state.write().execute_actions();
 }
 
+fn preview_pressed(state: RenamerState) {
+    log::trace!("Preview Pressed callback triggered");
+    state.write().plan_actions();
+}
+
+fn undo_pressed(state: RenamerState) {
+    log::trace!("Undo Pressed callback triggered");
+    state.write().undo_last();
+}
+
+fn cancel_pressed(state: RenamerState) {
+    log::trace!("Cancel Pressed callback triggered");
+    state.write().cancel();
+}
+
 fn cleanup(state: RenamerState) {
     state.write().cleanup();
 }
@@ -56,6 +77,8 @@ pub fn set_callbacks(window: &RenamerWindow, state: RenamerState) {
     let s = state.clone();
     window.on_remove_file(move |group_id, file_id| remove_file(group_id, file_id, s.clone()));
     let s = state.clone();
+    window.on_set_sort_order(move |group_id, order| set_sort_order(group_id, &order, s.clone()));
+    let s = state.clone();
     window.on_add_action(move |group_id, s_action| add_action(group_id, s_action, s.clone()));
     let s = state.clone();
     window
@@ -67,6 +90,12 @@ pub fn set_callbacks(window: &RenamerWindow, state: RenamerState) {
     let s = state.clone();
     window.on_on_go_pressed(move || go_pressed(s.clone()));
     let s = state.clone();
+    window.on_on_preview_pressed(move || preview_pressed(s.clone()));
+    let s = state.clone();
+    window.on_on_undo_pressed(move || undo_pressed(s.clone()));
+    let s = state.clone();
+    window.on_on_cancel_pressed(move || cancel_pressed(s.clone()));
+    let s = state.clone();
     window.on_cleanup(move || cleanup(s.clone()));
     let weak_window = window.as_weak();
     window.on_refresh_state(move || refresh_state(weak_window.clone(), state.clone()));
@@ -1,20 +1,37 @@
 use std::{
+    path::{Path, PathBuf},
     sync::mpsc,
     thread::{self, JoinHandle},
 };
 
-use crate::{SendableErr, state::RenamerState};
-use renamer_lib::{ActionGroup, report::Report};
+use crate::state::RenamerState;
+use renamer_lib::{
+    ActionGroup,
+    journal::{Journal, JournalError},
+    progress::{CancellationToken, Progress},
+    report::Report,
+};
 
 #[derive(Debug)]
 pub enum ToLibMessage {
     ExecuteActions(Vec<ActionGroup>),
+    PlanActions(Vec<ActionGroup>),
+    UndoLast,
+    /// Stop the running execution from starting any more renames.
+    Cancel,
     Cleanup,
 }
 #[derive(Debug)]
 pub enum FromLibMessage {
     SuccessfulActions(Vec<Report>),
-    UnsuccessfulActions(Vec<SendableErr>),
+    UnsuccessfulActions(Vec<Report>),
+    Planned(Vec<Report>),
+    JournalFailed(JournalError),
+    Progress {
+        done: usize,
+        total: usize,
+        current: PathBuf,
+    },
 }
 
 impl FromLibMessage {}
@@ -26,10 +43,22 @@ pub type FromLibReciever = mpsc::Receiver<FromLibMessage>;
 struct LibWrapper {
     sender: FromLibSender,
     receiver: ToLibReciever,
+    journal: Option<Journal>,
+    /// The execution currently running on its own thread, so that the event loop is
+    /// free to receive [`ToLibMessage::Cancel`].
+    running: Option<(JoinHandle<()>, CancellationToken)>,
 }
 impl LibWrapper {
     fn new(sender: FromLibSender, receiver: ToLibReciever) -> Self {
-        Self { sender, receiver }
+        let journal = Journal::open_default()
+            .inspect_err(|e| log::error!("Undo journal unavailable!: {e}"))
+            .ok();
+        Self {
+            sender,
+            receiver,
+            journal,
+            running: None,
+        }
     }
     fn event_loop(mut self) {
         loop {
@@ -47,44 +76,126 @@ impl LibWrapper {
                 }
             }
         }
+        if let Some((handle, cancel)) = self.running.take() {
+            cancel.cancel();
+            let _ = handle.join();
+        }
     }
     fn handle_message(&mut self, msg: ToLibMessage) {
         match msg {
             ToLibMessage::ExecuteActions(act_groups) => {
                 self.handle_execute_actions(act_groups);
             }
+            ToLibMessage::PlanActions(act_groups) => {
+                self.handle_plan_actions(act_groups);
+            }
+            ToLibMessage::UndoLast => self.handle_undo_last(),
+            ToLibMessage::Cancel => {
+                if let Some((_, cancel)) = &self.running {
+                    cancel.cancel();
+                }
+            }
             ToLibMessage::Cleanup => unreachable!(),
         }
     }
 
+    fn handle_plan_actions(&mut self, act_groups: Vec<ActionGroup>) {
+        let reports = act_groups.iter().flat_map(|x| x.plan().preview()).collect();
+        let _ = self
+            .sender
+            .send(FromLibMessage::Planned(reports))
+            .inspect_err(|x| log::error!("{x}"));
+    }
+
+    fn is_running(&self) -> bool {
+        self.running
+            .as_ref()
+            .is_some_and(|(handle, _)| !handle.is_finished())
+    }
+
     fn handle_execute_actions(&mut self, act_groups: Vec<ActionGroup>) {
-        let results = act_groups.into_iter().map(|x| x.execute()).flatten();
+        if self.is_running() {
+            log::warn!("Ignoring execute request, an execution is already running");
+            return;
+        }
+        let cancel = CancellationToken::new();
+        let sender = self.sender.clone();
+        let journal = self.journal.clone();
+        let token = cancel.clone();
+        let handle = thread::spawn(move || {
+            let plans: Vec<_> = act_groups.iter().map(ActionGroup::plan).collect();
+            let total = plans.iter().map(|plan| plan.len()).sum();
+            let mut results = Vec::new();
+            let mut offset = 0;
+            for plan in plans {
+                let len = plan.len();
+                let observer = |progress: Progress<'_>| {
+                    send_progress(&sender, offset + progress.done, total, progress.current)
+                };
+                results.extend(plan.execute_with(&observer, &token));
+                offset += len;
+            }
+            if let Some(journal) = &journal {
+                let _ = journal
+                    .record(results.iter())
+                    .inspect_err(|e| log::error!("Could not record renames!: {e}"));
+            }
+            send_results(&sender, results);
+        });
+        self.running = Some((handle, cancel));
+    }
 
-        let (successes, errors) =
-            results
-                .into_iter()
-                .fold((Vec::new(), Vec::new()), |(mut succs, mut errs), x| {
-                    match x {
-                        Ok(v) => succs.push(v),
-                        Err(e) => errs.push(e),
-                    };
-                    (succs, errs)
-                });
-        if !successes.is_empty() {
-            let _ = self
-                .sender
-                .send(FromLibMessage::SuccessfulActions(successes))
-                .inspect_err(|x| log::error!("{x}"));
+    fn handle_undo_last(&mut self) {
+        if self.is_running() {
+            log::warn!("Ignoring undo request, an execution is still running");
+            return;
+        }
+        let Some(journal) = &self.journal else {
+            log::error!("Cannot undo without a journal");
+            return;
         };
-        if !errors.is_empty() {
-            let _ = self
-                .sender
-                .send(FromLibMessage::UnsuccessfulActions(errors))
-                .inspect_err(|x| log::error!("{x}"));
+        match journal.undo_last(1) {
+            Ok(results) => send_results(&self.sender, results),
+            Err(e) => {
+                let _ = self
+                    .sender
+                    .send(FromLibMessage::JournalFailed(e))
+                    .inspect_err(|x| log::error!("{x}"));
+            }
         }
     }
 }
 
+/// Only every hundredth of the way through is sent, so a large batch doesn't flood the
+/// GUI with redraws.
+fn send_progress(sender: &FromLibSender, done: usize, total: usize, current: &Path) {
+    let step = (total / 100).max(1);
+    if !done.is_multiple_of(step) && done != total {
+        return;
+    }
+    let _ = sender
+        .send(FromLibMessage::Progress {
+            done,
+            total,
+            current: current.to_owned(),
+        })
+        .inspect_err(|x| log::error!("{x}"));
+}
+
+fn send_results(sender: &FromLibSender, results: Vec<Report>) {
+    let (errors, successes): (Vec<_>, Vec<_>) = results.into_iter().partition(Report::is_failure);
+    if !successes.is_empty() || errors.is_empty() {
+        let _ = sender
+            .send(FromLibMessage::SuccessfulActions(successes))
+            .inspect_err(|x| log::error!("{x}"));
+    };
+    if !errors.is_empty() {
+        let _ = sender
+            .send(FromLibMessage::UnsuccessfulActions(errors))
+            .inspect_err(|x| log::error!("{x}"));
+    }
+}
+
 pub fn setup() -> (JoinHandle<()>, ToLibSender, FromLibReciever) {
     let (lib_tx, gui_rx) = mpsc::channel::<FromLibMessage>();
     let (gui_tx, lib_rx) = mpsc::channel::<ToLibMessage>();
@@ -3,7 +3,6 @@ mod lib_thread;
 mod state;
 use std::error::Error;
 
-pub(crate) type SendableErr = Box<dyn Error + Send + Sync>;
 use callbacks::set_callbacks;
 use slint::ComponentHandle;
 slint::include_modules!();
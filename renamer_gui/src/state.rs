@@ -1,21 +1,22 @@
-use itertools::Itertools;
 use parking_lot::RwLock;
 use slint::{ModelRc, SharedString, ToSharedString, Weak, format};
 use std::{
     collections::HashMap,
     error::Error,
     fmt::Debug,
-    path::PathBuf,
+    path::{Path, PathBuf},
     sync::Arc,
     thread::{self, JoinHandle},
 };
 
 use crate::lib_thread::{self, FromLibMessage, ToLibMessage, ToLibSender};
 use crate::slint_generatedRenamerWindow::{
-    RenamerWindow, S_Action, S_ActionGroup, S_ActionOptions, S_File, S_Preset,
+    RenamerWindow, S_Action, S_ActionGroup, S_ActionOptions, S_ConflictPolicy, S_File, S_Preset,
 };
 use renamer_lib::{
-    ActionGroup,
+    ActionGroup, ConflictPolicy, SortOrder,
+    conflict::DEFAULT_COUNTER_FORMAT,
+    error::RenameError,
     patterns::{ActionOptions, RenamePattern},
     report::Report,
 };
@@ -44,7 +45,7 @@ pub struct Renamer {
 }
 impl Debug for Renamer {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "Action Groups: {:?}\n", self.action_groups)
+        writeln!(f, "Action Groups: {:?}", self.action_groups)
     }
 }
 
@@ -91,11 +92,9 @@ impl Renamer {
             log::error!("Non existent action group id!: {} for state {:?}", id, self)
         }
     }
-    pub fn add_patterns_to_group(&mut self, id: i32, patterns: Vec<RenamePattern>) {
+    pub fn set_sort_order(&mut self, id: i32, order: SortOrder) {
         if let Some(group) = self.action_groups.get_mut(&id) {
-            for action in patterns {
-                group.add_pattern(action);
-            }
+            group.set_sort_order(order);
         } else {
             log::error!("Non existent action group id!: {} for state {:?}", id, self)
         }
@@ -114,7 +113,7 @@ impl Renamer {
     }
     pub fn remove_action_from_group(&mut self, group_id: i32, action_id: i32) {
         if let Some(group) = self.action_groups.get_mut(&group_id) {
-            group.patterns_mut().remove(&action_id);
+            group.remove_pattern(action_id);
         }
     }
     pub fn send_message(&mut self, msg: ToLibMessage) {
@@ -122,7 +121,18 @@ impl Renamer {
     }
     pub fn execute_actions(&mut self) {
         self.send_message(ToLibMessage::ExecuteActions(
-            self.action_groups.values().map(|x| x.clone()).collect(),
+            self.action_groups.values().cloned().collect(),
+        ));
+    }
+    pub fn undo_last(&mut self) {
+        self.send_message(ToLibMessage::UndoLast);
+    }
+    pub fn cancel(&mut self) {
+        self.send_message(ToLibMessage::Cancel);
+    }
+    pub fn plan_actions(&mut self) {
+        self.send_message(ToLibMessage::PlanActions(
+            self.action_groups.values().cloned().collect(),
         ));
     }
     pub fn handle_message(&mut self, msg: FromLibMessage) {
@@ -136,6 +146,7 @@ impl Renamer {
                 let _ = self
                     .window
                     .upgrade_in_event_loop(move |window| {
+                        window.set_preview(false);
                         window.set_successes(successes.as_slice().into());
                         window.set_state_flag(crate::StateFlag::Finished);
                     })
@@ -144,11 +155,58 @@ impl Renamer {
             FromLibMessage::UnsuccessfulActions(vec) => {
                 let failures = vec
                     .into_iter()
-                    .map(|x| x.to_shared_string())
+                    .map(report_to_slint_string)
                     .collect::<Vec<_>>();
                 let _ = self
                     .window
                     .upgrade_in_event_loop(move |window| {
+                        window.set_preview(false);
+                        window.set_failures(failures.as_slice().into());
+                        window.set_state_flag(crate::StateFlag::Finished);
+                    })
+                    .inspect_err(|e| log::error!("Error handling message!: {e}"));
+            }
+            FromLibMessage::JournalFailed(err) => {
+                let failures = [err.to_shared_string()];
+                let _ = self
+                    .window
+                    .upgrade_in_event_loop(move |window| {
+                        window.set_preview(false);
+                        window.set_failures(failures.as_slice().into());
+                        window.set_state_flag(crate::StateFlag::Finished);
+                    })
+                    .inspect_err(|e| log::error!("Error handling message!: {e}"));
+            }
+            FromLibMessage::Progress {
+                done,
+                total,
+                current,
+            } => {
+                let text = format!("{done}/{total}: {}", current.display());
+                let _ = self
+                    .window
+                    .upgrade_in_event_loop(move |window| {
+                        window.set_progress(done as f32 / total.max(1) as f32);
+                        window.set_progress_text(text);
+                    })
+                    .inspect_err(|e| log::error!("Error handling message!: {e}"));
+            }
+            FromLibMessage::Planned(reports) => {
+                let (failures, planned): (Vec<_>, Vec<_>) =
+                    reports.into_iter().partition(Report::is_failure);
+                let planned = planned
+                    .into_iter()
+                    .map(report_to_slint_string)
+                    .collect::<Vec<_>>();
+                let failures = failures
+                    .into_iter()
+                    .map(report_to_slint_string)
+                    .collect::<Vec<_>>();
+                let _ = self
+                    .window
+                    .upgrade_in_event_loop(move |window| {
+                        window.set_preview(true);
+                        window.set_successes(planned.as_slice().into());
                         window.set_failures(failures.as_slice().into());
                         window.set_state_flag(crate::StateFlag::Finished);
                     })
@@ -163,23 +221,56 @@ fn report_to_slint_string(report: Report) -> SharedString {
         Report::Renamed {
             from,
             to,
-            overwrote,
+            displaced,
+            copied,
+            ..
         } => {
             let mut out = format!("Renamed: \n {from:?} \n to \n {to:?}");
-            if overwrote {
-                out.push_str("\n (OVERWROTE)");
+            if let Some(displaced) = displaced {
+                out.push_str(&format!("\n (EXISTING MOVED TO {displaced:?})"));
+            }
+            if copied {
+                out.push_str("\n (COPIED ACROSS FILESYSTEMS)");
+            }
+            out
+        }
+        Report::Overwrote {
+            from, to, copied, ..
+        } => {
+            let mut out = format!("Renamed: \n {from:?} \n to \n {to:?} \n (OVERWROTE)");
+            if copied {
+                out.push_str("\n (COPIED ACROSS FILESYSTEMS)");
             }
-            SharedString::from(out)
+            out
         }
-        Report::Nothing => SharedString::from(""),
+        Report::Skipped {
+            path,
+            target,
+            reason,
+            ..
+        } => {
+            format!("Skipped: \n {path:?} \n to \n {target:?} \n {reason}")
+        }
+        Report::Failed { error, .. } => error_to_slint_string(error),
+        Report::Planned { from, to, .. } => format!("Will rename: \n {from:?} \n to \n {to:?}"),
+        Report::Unchanged { path, .. } => format!("Unchanged: \n {path:?}"),
+    }
+}
+
+fn error_to_slint_string(err: RenameError) -> SharedString {
+    let mut out = format!("Failed: \n {:?}", err.path());
+    if let Some(target) = err.target() {
+        out.push_str(&format!(" \n to \n {target:?}"));
     }
+    out.push_str(&format!(" \n {}", err.cause()));
+    out
 }
 
-impl Into<S_File> for (&i32, &PathBuf) {
-    fn into(self) -> S_File {
+impl From<(i32, &Path)> for S_File {
+    fn from((id, path): (i32, &Path)) -> Self {
         S_File {
-            id: *self.0,
-            path: self.1.to_string_lossy().to_string().into(),
+            id,
+            path: path.to_string_lossy().to_string().into(),
         }
     }
 }
@@ -190,36 +281,61 @@ impl TryInto<RenamePattern> for S_Action {
     fn try_into(self) -> Result<RenamePattern, Self::Error> {
         match self.preset {
             S_Preset::Randomize => Ok(RenamePattern::randomize(self.options.into())),
-            S_Preset::Custom => RenamePattern::parse(self.pattern.as_str(), self.options.into())
-                .map_err(|x| x.into()),
+            S_Preset::Custom => RenamePattern::parse(self.pattern.as_str(), self.options.into()),
+            S_Preset::Replace => Ok(RenamePattern::replace(
+                self.pattern.as_str(),
+                self.options.into(),
+            )?),
         }
     }
 }
 
-impl Into<ActionOptions> for S_ActionOptions {
-    fn into(self) -> ActionOptions {
+impl From<S_ActionOptions> for ActionOptions {
+    fn from(options: S_ActionOptions) -> Self {
+        let on_conflict = match options.on_conflict {
+            S_ConflictPolicy::Fail => ConflictPolicy::Fail,
+            S_ConflictPolicy::Skip => ConflictPolicy::Skip,
+            S_ConflictPolicy::Overwrite => ConflictPolicy::Overwrite,
+            S_ConflictPolicy::AppendCounter if options.counter_format.is_empty() => {
+                ConflictPolicy::AppendCounter(DEFAULT_COUNTER_FORMAT.to_owned())
+            }
+            S_ConflictPolicy::AppendCounter => {
+                ConflictPolicy::AppendCounter(options.counter_format.to_string())
+            }
+            S_ConflictPolicy::KeepBoth => ConflictPolicy::KeepBoth,
+        };
         ActionOptions {
-            overwrite: self.overwrite,
-            preserve_file_extension: self.preserve_file_extension,
+            on_conflict,
+            preserve_file_extension: options.preserve_file_extension,
         }
     }
 }
-impl Into<S_ActionOptions> for ActionOptions {
-    fn into(self) -> S_ActionOptions {
+impl From<&ActionOptions> for S_ActionOptions {
+    fn from(options: &ActionOptions) -> Self {
+        let (on_conflict, counter_format) = match &options.on_conflict {
+            ConflictPolicy::Fail => (S_ConflictPolicy::Fail, ""),
+            ConflictPolicy::Skip => (S_ConflictPolicy::Skip, ""),
+            ConflictPolicy::Overwrite => (S_ConflictPolicy::Overwrite, ""),
+            ConflictPolicy::AppendCounter(format) => {
+                (S_ConflictPolicy::AppendCounter, format.as_str())
+            }
+            ConflictPolicy::KeepBoth => (S_ConflictPolicy::KeepBoth, ""),
+        };
         S_ActionOptions {
-            overwrite: self.overwrite,
-            preserve_file_extension: self.preserve_file_extension,
+            on_conflict,
+            counter_format: counter_format.into(),
+            preserve_file_extension: options.preserve_file_extension,
         }
     }
 }
 
-impl Into<S_Action> for (&i32, &RenamePattern) {
-    fn into(self) -> S_Action {
+impl From<(&i32, &RenamePattern)> for S_Action {
+    fn from((id, pattern): (&i32, &RenamePattern)) -> Self {
         S_Action {
-            pattern: self.1.input().cloned().unwrap_or("".to_owned()).into(),
-            preset: self.1.preset_info().unwrap_or("Custom").into(),
-            id: *self.0,
-            options: self.1.options().into(),
+            pattern: pattern.input().cloned().unwrap_or("".to_owned()).into(),
+            preset: pattern.preset_info().unwrap_or("Custom").into(),
+            id: *id,
+            options: pattern.options().into(),
         }
     }
 }
@@ -229,6 +345,7 @@ impl From<&str> for S_Preset {
         match value {
             "Randomize" => S_Preset::Randomize,
             "Custom" => S_Preset::Custom,
+            "Replace" => S_Preset::Replace,
             _ => {
                 log::error!("Unrecognized preset!");
                 S_Preset::Custom
@@ -237,26 +354,24 @@ impl From<&str> for S_Preset {
     }
 }
 
-impl Into<S_ActionGroup> for (&i32, &ActionGroup) {
-    fn into(self) -> S_ActionGroup {
-        let (id, group) = self;
+impl From<(&i32, &ActionGroup)> for S_ActionGroup {
+    fn from((id, group): (&i32, &ActionGroup)) -> Self {
         S_ActionGroup {
             actions: group
                 .patterns()
                 .iter()
-                .map(|x| x.into())
-                .sorted_by_key(|x: &S_Action| x.id)
-                .collect::<Vec<_>>()
+                .map(|(id, pattern)| (id, pattern).into())
+                .collect::<Vec<S_Action>>()
                 .as_slice()
                 .into(),
             files: group
-                .files()
-                .iter()
+                .sorted_files()
+                .into_iter()
                 .map(|x| x.into())
-                .sorted_by_key(|x: &S_File| x.id)
-                .collect::<Vec<_>>()
+                .collect::<Vec<S_File>>()
                 .as_slice()
                 .into(),
+            sort_order: group.sort_order().to_shared_string(),
             id: *id,
         }
     }
@@ -0,0 +1,176 @@
+use std::{
+    fmt::Display,
+    fs, io,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::{moves, noclobber};
+
+/// Counter format used by [`ConflictPolicy::KeepBoth`] and by `append-counter` when no
+/// format is given, e.g. `notes.txt` becomes `notes (1).txt`.
+pub const DEFAULT_COUNTER_FORMAT: &str = " ({})";
+
+/// What a rename does when its target already exists.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    /// Leave the file under its old name.
+    Skip,
+    /// Replace the existing target.
+    Overwrite,
+    /// Leave the file under its old name and report an error.
+    #[default]
+    Fail,
+    /// Give the renamed file a free name by adding a counter before its extension. The
+    /// first `{}` in the format is replaced by the counter, if there is none the counter
+    /// goes at the end of the format.
+    AppendCounter(String),
+    /// Move the existing target aside with [`DEFAULT_COUNTER_FORMAT`] so the renamed
+    /// file still gets its intended name.
+    KeepBoth,
+}
+
+#[derive(Error, Debug)]
+#[error(
+    "Unknown conflict policy {0:?}, expected one of skip, overwrite, fail, append-counter[=FORMAT], keep-both"
+)]
+pub struct ParseConflictPolicyError(String);
+
+impl FromStr for ConflictPolicy {
+    type Err = ParseConflictPolicyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "skip" => Ok(Self::Skip),
+            "overwrite" => Ok(Self::Overwrite),
+            "fail" => Ok(Self::Fail),
+            "keep-both" => Ok(Self::KeepBoth),
+            "append-counter" => Ok(Self::AppendCounter(DEFAULT_COUNTER_FORMAT.to_owned())),
+            _ => match s.strip_prefix("append-counter=") {
+                Some(format) => Ok(Self::AppendCounter(format.to_owned())),
+                None => Err(ParseConflictPolicyError(s.to_owned())),
+            },
+        }
+    }
+}
+
+impl Display for ConflictPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConflictPolicy::Skip => write!(f, "skip"),
+            ConflictPolicy::Overwrite => write!(f, "overwrite"),
+            ConflictPolicy::Fail => write!(f, "fail"),
+            ConflictPolicy::AppendCounter(format) => write!(f, "append-counter={format}"),
+            ConflictPolicy::KeepBoth => write!(f, "keep-both"),
+        }
+    }
+}
+
+impl Serialize for ConflictPolicy {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Renames `from` to the first name derived from `target` with a counter in `format`
+/// that is free at the moment of the rename. Returns that name and whether the file had
+/// to be copied, see [`moves::move_path`].
+pub(crate) fn rename_with_counter(
+    from: &Path,
+    target: &Path,
+    format: &str,
+) -> io::Result<(PathBuf, bool)> {
+    let mut count: u32 = 1;
+    loop {
+        let candidate = with_counter(target, format, count);
+        match moves::move_path(from, &candidate, false) {
+            Ok(copied) => return Ok((candidate, copied)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => count += 1,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Moves a file displaced by [`ConflictPolicy::KeepBoth`] back to its original name.
+pub(crate) fn restore_displaced(aside: &Path, original: &Path) {
+    match fs::exists(original) {
+        Ok(false) => match noclobber::rename(aside, original) {
+            Ok(()) => log::info!("Restored {original:?} from {aside:?}"),
+            Err(e) => log::error!("Could not restore {original:?} from {aside:?}!: {e}"),
+        },
+        _ => log::error!("Could not restore {original:?}, it was left at {aside:?}"),
+    }
+}
+
+fn with_counter(p: &Path, format: &str, count: u32) -> PathBuf {
+    let suffix = if format.contains("{}") {
+        format.replacen("{}", &count.to_string(), 1)
+    } else {
+        format!("{format}{count}")
+    };
+    let mut name = p.file_stem().unwrap_or_default().to_os_string();
+    name.push(suffix);
+    if let Some(ext) = p.extension() {
+        name.push(".");
+        name.push(ext);
+    }
+    p.with_file_name(name)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, path::Path};
+
+    use super::{ConflictPolicy, rename_with_counter, with_counter};
+
+    #[test]
+    fn parse_round_trip() {
+        for policy in [
+            ConflictPolicy::Skip,
+            ConflictPolicy::Overwrite,
+            ConflictPolicy::Fail,
+            ConflictPolicy::AppendCounter("_{}".to_owned()),
+            ConflictPolicy::KeepBoth,
+        ] {
+            assert_eq!(
+                policy.to_string().parse::<ConflictPolicy>().unwrap(),
+                policy
+            );
+        }
+        assert!("sometimes".parse::<ConflictPolicy>().is_err());
+    }
+
+    #[test]
+    fn counter_goes_before_extension() {
+        let p = Path::new("/dir/notes.txt");
+        assert_eq!(with_counter(p, " ({})", 2), Path::new("/dir/notes (2).txt"));
+        assert_eq!(with_counter(p, "-", 3), Path::new("/dir/notes-3.txt"));
+        assert_eq!(
+            with_counter(Path::new("/dir/README"), "_{}", 1),
+            Path::new("/dir/README_1")
+        );
+    }
+
+    #[test]
+    fn counter_skips_taken_names() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["a.txt", "a (1).txt", "a (2).txt", "b.txt"] {
+            fs::write(dir.path().join(name), name).unwrap();
+        }
+        let (renamed, _) = rename_with_counter(
+            &dir.path().join("b.txt"),
+            &dir.path().join("a.txt"),
+            " ({})",
+        )
+        .unwrap();
+        assert_eq!(renamed, dir.path().join("a (3).txt"));
+        assert_eq!(fs::read_to_string(renamed).unwrap(), "b.txt");
+        assert_eq!(
+            fs::read_to_string(dir.path().join("a.txt")).unwrap(),
+            "a.txt"
+        );
+    }
+}
@@ -1,23 +1,160 @@
-use std::error::Error;
+use std::{fmt::Display, io, path::Path, path::PathBuf};
+
+use serde::{Serialize, ser::SerializeStruct};
 use thiserror::Error;
 
-pub(crate) type SendableErr = Box<dyn Error + Send + Sync>;
+use crate::{
+    patterns::Timestamp,
+    plan::{Collision, Rollback},
+};
+
+/// A failed rename of a single file.
+#[derive(Error, Debug)]
+pub struct RenameError {
+    path: PathBuf,
+    target: Option<PathBuf>,
+    #[source]
+    cause: ActionError,
+}
+
+impl RenameError {
+    pub fn new(path: PathBuf, target: Option<PathBuf>, cause: impl Into<ActionError>) -> Self {
+        Self {
+            path,
+            target,
+            cause: cause.into(),
+        }
+    }
+
+    /// The file that was being renamed.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The name the file was going to be given, if it got as far as computing one.
+    pub fn target(&self) -> Option<&Path> {
+        self.target.as_deref()
+    }
+
+    pub fn cause(&self) -> &ActionError {
+        &self.cause
+    }
+
+    pub fn into_cause(self) -> ActionError {
+        self.cause
+    }
+
+    pub fn category(&self) -> ErrorCategory {
+        self.cause.category()
+    }
+}
+
+impl Display for RenameError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.target {
+            Some(target) => write!(
+                f,
+                "{} -> {}: {}",
+                self.path.display(),
+                target.display(),
+                self.cause
+            ),
+            None => write!(f, "{}: {}", self.path.display(), self.cause),
+        }
+    }
+}
+
+/// Serialized as the path and target, the [`ErrorCategory`] and the cause's message.
+impl Serialize for RenameError {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut s = serializer.serialize_struct("RenameError", 4)?;
+        s.serialize_field("path", &self.path)?;
+        s.serialize_field("target", &self.target)?;
+        s.serialize_field("category", &self.category())?;
+        s.serialize_field("message", &self.cause.to_string())?;
+        s.end()
+    }
+}
+
+/// Broad reason a rename failed, for callers that want to react to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCategory {
+    NotFound,
+    PermissionDenied,
+    TargetExists,
+    InvalidName,
+    Io,
+    Pattern,
+    /// The file or its filesystem doesn't provide something the pattern needs, e.g. a
+    /// creation time.
+    Unsupported,
+    /// The rename was not attempted because something it relies on was not in the
+    /// expected state, e.g. another rename in the same batch failed.
+    Blocked,
+}
 
 #[derive(Error, Debug)]
 pub enum ActionError {
     #[error("Cannot rename a path ending in \"..\"")]
     CannotRenameDotDot,
+    #[error("Invalid file name {0:?}")]
+    InvalidName(String),
+    #[error("Could not apply pattern: {0}")]
+    Pattern(String),
+    #[error("{0}")]
+    Collision(Collision),
+    #[error("Not renamed because renaming {blocked_by:?} failed")]
+    DependencyFailed { blocked_by: PathBuf },
+    #[error("{0}")]
+    RolledBack(Box<Rollback>),
+    #[error("Target already exists")]
+    TargetExists,
+    #[error("Cancelled")]
+    Cancelled,
+    #[error("File has changed since it was renamed")]
+    Modified,
+    #[error("The name {0:?} doesn't match the pattern's regex")]
+    NoMatch(String),
+    #[error("The file has no {0}")]
+    MissingMetadata(String),
+    #[error("The file's {timestamp} is unavailable: {source}")]
+    TimestampUnavailable {
+        timestamp: Timestamp,
+        source: io::Error,
+    },
     #[error("{0}")]
-    Other(SendableErr),
-    #[error("Unknown")]
-    Unknown,
+    Io(#[from] io::Error),
 }
 
-impl From<SendableErr> for ActionError {
-    fn from(v: SendableErr) -> Self {
-        match v.downcast::<Self>() {
-            Ok(x) => *x,
-            Err(e) => ActionError::Other(e),
+impl ActionError {
+    pub fn category(&self) -> ErrorCategory {
+        match self {
+            ActionError::CannotRenameDotDot | ActionError::InvalidName(_) => {
+                ErrorCategory::InvalidName
+            }
+            ActionError::Pattern(_) | ActionError::NoMatch(_) => ErrorCategory::Pattern,
+            ActionError::Collision(_) | ActionError::TargetExists => ErrorCategory::TargetExists,
+            ActionError::DependencyFailed { .. }
+            | ActionError::Modified
+            | ActionError::Cancelled => ErrorCategory::Blocked,
+            ActionError::TimestampUnavailable { .. } | ActionError::MissingMetadata(_) => {
+                ErrorCategory::Unsupported
+            }
+            ActionError::RolledBack(rollback) => rollback.cause().category(),
+            ActionError::Io(e) => match e.kind() {
+                io::ErrorKind::NotFound => ErrorCategory::NotFound,
+                io::ErrorKind::PermissionDenied | io::ErrorKind::ReadOnlyFilesystem => {
+                    ErrorCategory::PermissionDenied
+                }
+                io::ErrorKind::AlreadyExists | io::ErrorKind::DirectoryNotEmpty => {
+                    ErrorCategory::TargetExists
+                }
+                io::ErrorKind::InvalidFilename | io::ErrorKind::InvalidInput => {
+                    ErrorCategory::InvalidName
+                }
+                _ => ErrorCategory::Io,
+            },
         }
     }
 }
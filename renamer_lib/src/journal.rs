@@ -0,0 +1,373 @@
+use std::{
+    collections::HashSet,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    Action, ConflictPolicy, conflict,
+    error::{ActionError, RenameError},
+    plan::RenamePlan,
+    report::Report,
+};
+
+#[derive(Error, Debug)]
+pub enum JournalError {
+    #[error("Could not find a data directory for the journal")]
+    NoDataDir,
+    #[error("Journal IO Error!: {0}")]
+    Io(#[from] io::Error),
+    #[error("Malformed journal entry!: {0}")]
+    Parse(#[from] serde_json::Error),
+    #[error("No batch with id {0} in the journal")]
+    NoSuchBatch(u64),
+}
+
+/// A completed rename as recorded in the journal.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct JournalEntry {
+    pub from: PathBuf,
+    pub to: PathBuf,
+    pub overwrote: bool,
+    /// Where an existing file at `to` was moved to make room for the rename.
+    #[serde(default)]
+    pub displaced: Option<PathBuf>,
+    len: u64,
+    modified: Option<SystemTime>,
+}
+
+impl JournalEntry {
+    fn new(
+        from: PathBuf,
+        to: PathBuf,
+        overwrote: bool,
+        displaced: Option<PathBuf>,
+    ) -> io::Result<Self> {
+        let metadata = fs::symlink_metadata(&to)?;
+        Ok(Self {
+            from,
+            to,
+            overwrote,
+            displaced,
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+        })
+    }
+
+    /// Checks the renamed file is still where it was left, unchanged.
+    fn verify(&self) -> Result<(), ActionError> {
+        let metadata = fs::symlink_metadata(&self.to)?;
+        if metadata.len() != self.len || metadata.modified().ok() != self.modified {
+            return Err(ActionError::Modified);
+        }
+        Ok(())
+    }
+}
+
+/// Every rename performed by one executed plan.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Batch {
+    pub id: u64,
+    pub timestamp: DateTime<Local>,
+    pub entries: Vec<JournalEntry>,
+}
+
+/// Append only log of completed batches, stored as one JSON object per line.
+#[derive(Debug, Clone)]
+pub struct Journal {
+    path: PathBuf,
+}
+
+impl Journal {
+    /// The journal in the user's data directory, e.g. `~/.local/share/renamer/journal.jsonl`.
+    pub fn open_default() -> Result<Self, JournalError> {
+        let dir = dirs::data_dir().ok_or(JournalError::NoDataDir)?;
+        Ok(Self::at(dir.join("renamer").join("journal.jsonl")))
+    }
+
+    pub fn at(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// All recorded batches, oldest first.
+    pub fn batches(&self) -> Result<Vec<Batch>, JournalError> {
+        let file = match File::open(&self.path) {
+            Ok(f) => f,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut batches = Vec::new();
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            if !line.trim().is_empty() {
+                batches.push(serde_json::from_str(&line)?);
+            }
+        }
+        Ok(batches)
+    }
+
+    /// Records the renames in `reports` as a new batch, returning its id. Nothing is
+    /// written if no file was renamed. A renamed file that can no longer be read is left
+    /// out with a warning, so the rest of the batch can still be undone.
+    pub fn record<'a>(
+        &self,
+        reports: impl IntoIterator<Item = &'a Report>,
+    ) -> Result<Option<u64>, JournalError> {
+        let mut entries = Vec::new();
+        for report in reports {
+            let entry = match report {
+                Report::Renamed {
+                    from,
+                    to,
+                    displaced,
+                    ..
+                } => JournalEntry::new(from.clone(), to.clone(), false, displaced.clone()),
+                Report::Overwrote { from, to, .. } => {
+                    JournalEntry::new(from.clone(), to.clone(), true, None)
+                }
+                _ => continue,
+            };
+            match entry {
+                Ok(entry) => entries.push(entry),
+                Err(e) => log::warn!(
+                    "Could not record the rename of {:?} in the journal, it cannot be undone!: {e}",
+                    report.path()
+                ),
+            }
+        }
+        if entries.is_empty() {
+            return Ok(None);
+        }
+        let _lock = self.lock()?;
+        let now = Local::now();
+        let last_id = self.batches()?.last().map(|b| b.id).unwrap_or(0);
+        let id = (last_id + 1).max(now.timestamp_millis() as u64);
+        let batch = Batch {
+            id,
+            timestamp: now,
+            entries,
+        };
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(file, "{}", serde_json::to_string(&batch)?)?;
+        Ok(Some(id))
+    }
+
+    /// Reverts the `n` most recent batches, newest first.
+    pub fn undo_last(&self, n: usize) -> Result<Vec<Report>, JournalError> {
+        let batches = self.batches()?;
+        let mut results = Vec::new();
+        for batch in batches.iter().rev().take(n) {
+            results.extend(self.undo_batch(batch.id)?);
+        }
+        Ok(results)
+    }
+
+    /// Reverts a single batch. Entries whose file has gone missing or changed since it
+    /// was renamed are left alone and reported as errors, they stay in the journal so
+    /// the undo can be retried.
+    pub fn undo_batch(&self, id: u64) -> Result<Vec<Report>, JournalError> {
+        let _lock = self.lock()?;
+        let mut batches = self.batches()?;
+        let pos = batches
+            .iter()
+            .position(|b| b.id == id)
+            .ok_or(JournalError::NoSuchBatch(id))?;
+        let batch = &batches[pos];
+        for entry in batch.entries.iter().filter(|e| e.overwrote) {
+            log::warn!(
+                "{:?} replaced an existing file when it was renamed, that file cannot be restored",
+                entry.to
+            );
+        }
+
+        let vacated: HashSet<&Path> = batch.entries.iter().map(|e| e.to.as_path()).collect();
+        let mut results = Vec::new();
+        let mut actions = Vec::new();
+        let mut remaining = Vec::new();
+        for entry in batch.entries.iter() {
+            let start = Instant::now();
+            let checked = entry.verify().and_then(|_| {
+                if !vacated.contains(entry.from.as_path()) && fs::exists(&entry.from)? {
+                    Err(ActionError::TargetExists)
+                } else {
+                    Ok(())
+                }
+            });
+            match checked {
+                Ok(()) => actions.push(Ok(Action {
+                    old: entry.to.clone(),
+                    new: entry.from.clone(),
+                    on_conflict: ConflictPolicy::Fail,
+                    planned_in: Duration::ZERO,
+                })),
+                Err(e) => {
+                    results.push(Report::Failed {
+                        error: RenameError::new(entry.to.clone(), Some(entry.from.clone()), e),
+                        duration: start.elapsed(),
+                    });
+                    remaining.push(entry.clone());
+                }
+            }
+        }
+
+        let plan = RenamePlan::new(actions);
+        let sources: Vec<Action> = plan.actions().to_vec();
+        for (action, rep) in sources.iter().zip(plan.execute()) {
+            let entry = batch
+                .entries
+                .iter()
+                .find(|e| e.to == action.source())
+                .expect("Every undo action comes from an entry");
+            if rep.is_failure() {
+                remaining.push(entry.clone());
+            } else if let Some(aside) = &entry.displaced {
+                conflict::restore_displaced(aside, &entry.to);
+            }
+            results.push(rep);
+        }
+
+        if remaining.is_empty() {
+            batches.remove(pos);
+        } else {
+            batches[pos].entries = remaining;
+        }
+        self.write_all(&batches)?;
+        Ok(results)
+    }
+
+    /// Takes an exclusive lock for reading and then rewriting or appending to the journal,
+    /// so that concurrent runs don't hand out the same batch id or lose each other's
+    /// batches. A separate file is locked as [`Journal::write_all`] replaces the journal.
+    /// The lock is released when the returned file is dropped.
+    fn lock(&self) -> Result<File, JournalError> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(self.path.with_extension("jsonl.lock"))?;
+        file.lock()?;
+        Ok(file)
+    }
+
+    fn write_all(&self, batches: &[Batch]) -> Result<(), JournalError> {
+        let tmp = self.path.with_extension("jsonl.tmp");
+        let mut file = File::create(&tmp)?;
+        for batch in batches {
+            writeln!(file, "{}", serde_json::to_string(batch)?)?;
+        }
+        file.sync_all()?;
+        fs::rename(tmp, &self.path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use super::Journal;
+    use crate::{ActionGroup, RenamePattern, patterns::ActionOptions};
+
+    fn rename_all(dir: &std::path::Path, names: &[&str], pattern: &str) -> ActionGroup {
+        let mut group = ActionGroup::new(0);
+        for name in names {
+            let path = dir.join(name);
+            fs::write(&path, name).unwrap();
+            group.add_file(path);
+        }
+        group.add_pattern(RenamePattern::parse(pattern, ActionOptions::default()).unwrap());
+        group
+    }
+
+    #[test]
+    fn record_and_undo() {
+        let dir = tempfile::tempdir().unwrap();
+        let journal = Journal::at(dir.path().join("journal").join("journal.jsonl"));
+        let group = rename_all(dir.path(), &["a", "b"], "new_/ORIGINAL/");
+        let results = group.execute();
+        let id = journal
+            .record(results.iter())
+            .unwrap()
+            .expect("Files were renamed");
+        assert!(dir.path().join("new_a").exists());
+
+        let batches = journal.batches().unwrap();
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].id, id);
+        assert_eq!(batches[0].entries.len(), 2);
+
+        let undone = journal.undo_last(1).unwrap();
+        assert!(undone.iter().all(|r| !r.is_failure()));
+        assert_eq!(fs::read_to_string(dir.path().join("a")).unwrap(), "a");
+        assert_eq!(fs::read_to_string(dir.path().join("b")).unwrap(), "b");
+        assert!(journal.batches().unwrap().is_empty());
+    }
+
+    #[test]
+    fn record_skips_vanished_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let journal = Journal::at(dir.path().join("journal.jsonl"));
+        let mut results = rename_all(dir.path(), &["a", "b"], "new_/ORIGINAL/").execute();
+        fs::remove_file(dir.path().join("new_a")).unwrap();
+        results.retain(|r| !r.is_failure());
+        let id = journal.record(results.iter()).unwrap().unwrap();
+
+        let batches = journal.batches().unwrap();
+        assert_eq!(batches[0].id, id);
+        assert_eq!(batches[0].entries.len(), 1);
+        assert!(batches[0].entries[0].to.ends_with("new_b"));
+    }
+
+    #[test]
+    fn concurrent_records_get_unique_ids() {
+        let dir = tempfile::tempdir().unwrap();
+        let journal = Journal::at(dir.path().join("journal.jsonl"));
+        let names: Vec<String> = (0..8).map(|i| i.to_string()).collect();
+        let names: Vec<&str> = names.iter().map(String::as_str).collect();
+        let results = rename_all(dir.path(), &names, "new_/ORIGINAL/").execute();
+        let journal = &journal;
+        let ids: Vec<u64> = std::thread::scope(|s| {
+            let handles: Vec<_> = results
+                .iter()
+                .map(|report| s.spawn(move || journal.record([report]).unwrap().unwrap()))
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+        let unique: std::collections::HashSet<_> = ids.iter().collect();
+        assert_eq!(unique.len(), ids.len());
+        assert_eq!(journal.batches().unwrap().len(), ids.len());
+    }
+
+    #[test]
+    fn undo_refuses_changed_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let journal = Journal::at(dir.path().join("journal.jsonl"));
+        let group = rename_all(dir.path(), &["a", "b"], "new_/ORIGINAL/");
+        let results = group.execute();
+        let id = journal.record(results.iter()).unwrap().unwrap();
+        fs::write(dir.path().join("new_a"), "changed contents").unwrap();
+
+        let undone = journal.undo_batch(id).unwrap();
+        assert_eq!(undone.iter().filter(|r| r.is_failure()).count(), 1);
+        assert!(dir.path().join("new_a").exists());
+        assert!(dir.path().join("b").exists());
+        let batches = journal.batches().unwrap();
+        assert_eq!(batches[0].entries.len(), 1);
+    }
+}
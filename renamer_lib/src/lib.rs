@@ -1,20 +1,39 @@
+pub mod conflict;
 pub mod error;
+pub mod journal;
+mod moves;
+mod noclobber;
+pub mod order;
 pub mod patterns;
+pub mod plan;
+pub mod progress;
 pub mod report;
-use std::{collections::HashMap, fs, path::PathBuf};
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    fs, io,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
 
-use error::SendableErr;
+pub use conflict::ConflictPolicy;
+use error::{ActionError, RenameError};
+pub use order::SortOrder;
+use patterns::Position;
 pub use patterns::{PatternParseError, RenamePattern};
-use rayon::iter::{IntoParallelIterator, IntoParallelRefIterator, ParallelIterator};
-use report::Report;
+pub use plan::RenamePlan;
+use rayon::iter::{IndexedParallelIterator, IntoParallelRefIterator, ParallelIterator};
+use report::{Report, SkipReason};
+use serde::Serialize;
 
 #[derive(Default, Debug, Clone)]
 pub struct ActionGroup {
     id: i32,
     files: HashMap<i32, PathBuf>,
     next_file_id: i32,
-    patterns: HashMap<i32, (RenamePattern)>,
+    patterns: Vec<(i32, RenamePattern)>,
     next_action_id: i32,
+    sort_order: SortOrder,
 }
 
 impl ActionGroup {
@@ -32,12 +51,27 @@ impl ActionGroup {
         self.files.insert(self.next_file_id, file);
         self.next_file_id += 1;
     }
+    /// Appends `pattern` to the end of the group's pipeline.
     pub fn add_pattern(&mut self, pattern: RenamePattern) {
-        self.patterns.insert(self.next_action_id, pattern);
+        self.patterns.push((self.next_action_id, pattern));
         self.next_action_id += 1;
     }
 
-    pub fn patterns_mut(&mut self) -> &mut HashMap<i32, RenamePattern> {
+    pub fn remove_pattern(&mut self, id: i32) -> Option<RenamePattern> {
+        let pos = self.patterns.iter().position(|(x, _)| *x == id)?;
+        Some(self.patterns.remove(pos).1)
+    }
+
+    /// Moves the pattern with the given id to `index` in the pipeline, clamped to its end.
+    pub fn move_pattern(&mut self, id: i32, index: usize) {
+        if let Some(pos) = self.patterns.iter().position(|(x, _)| *x == id) {
+            let pattern = self.patterns.remove(pos);
+            let index = index.min(self.patterns.len());
+            self.patterns.insert(index, pattern);
+        }
+    }
+
+    pub fn patterns_mut(&mut self) -> &mut Vec<(i32, RenamePattern)> {
         &mut self.patterns
     }
 
@@ -49,76 +83,477 @@ impl ActionGroup {
         &self.files
     }
 
-    pub fn patterns(&self) -> &HashMap<i32, RenamePattern> {
+    /// The group's files with their ids, in its [`SortOrder`].
+    pub fn sorted_files(&self) -> Vec<(i32, &Path)> {
+        self.sort_order.sort(&self.files)
+    }
+
+    pub fn sort_order(&self) -> SortOrder {
+        self.sort_order
+    }
+
+    /// Sets the order files are planned, executed and reported in.
+    pub fn set_sort_order(&mut self, order: SortOrder) {
+        self.sort_order = order;
+    }
+
+    /// The group's patterns in the order they are applied.
+    pub fn patterns(&self) -> &[(i32, RenamePattern)] {
         &self.patterns
     }
 
-    pub fn set_patterns(&mut self, actions: HashMap<i32, RenamePattern>) {
+    pub fn set_patterns(&mut self, actions: Vec<(i32, RenamePattern)>) {
         self.patterns = actions;
     }
 
-    fn generate_actions(&self) -> Vec<Result<Action, SendableErr>> {
-        self.patterns
+    /// Every file gets a single action, with the patterns applied one after the other.
+    /// The actions are in the group's [`SortOrder`].
+    fn generate_actions(&self) -> Vec<Result<Action, (RenameError, Duration)>> {
+        if self.patterns.is_empty() {
+            return Vec::new();
+        }
+        let files = self.sorted_files();
+        let mut in_dir: HashMap<Option<&Path>, usize> = HashMap::new();
+        let positions: Vec<Position> = files
+            .iter()
+            .enumerate()
+            .map(|(index, (_, path))| {
+                let count = in_dir.entry(path.parent()).or_default();
+                *count += 1;
+                Position {
+                    index,
+                    in_dir: *count - 1,
+                }
+            })
+            .collect();
+        files
             .par_iter()
-            .map(|(_, pat)| {
-                self.files
-                    .par_iter()
-                    .map(|(_, path)| Action::new(path.clone(), pat))
-                    .collect::<Vec<_>>()
+            .zip(positions)
+            .map(|((_, path), position)| {
+                let start = Instant::now();
+                match Action::new(path.to_path_buf(), &self.patterns, position) {
+                    Ok(action) => Ok(Action {
+                        planned_in: start.elapsed(),
+                        ..action
+                    }),
+                    Err(e) => Err((e, start.elapsed())),
+                }
             })
-            .flatten()
             .collect()
     }
-    pub fn execute(&self) -> Vec<Result<Report, SendableErr>> {
-        self.generate_actions()
-            .into_par_iter()
-            .map(|res| match res {
-                Ok(act) => act.execute(),
-                Err(e) => Err(e),
-            })
-            .collect()
+    /// Computes every rename this group would perform without touching the disk.
+    pub fn plan(&self) -> RenamePlan {
+        RenamePlan::new(self.generate_actions())
+    }
+    pub fn execute(&self) -> Vec<Report> {
+        self.plan().execute()
+    }
+    /// Like [`ActionGroup::execute`], but reverts every rename if any of them fails.
+    /// See [`RenamePlan::execute_atomic`].
+    pub fn execute_atomic(&self) -> Vec<Report> {
+        self.plan().execute_atomic()
     }
 }
 
-struct Action {
+/// A single computed rename of `source` to `target`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Action {
+    #[serde(rename = "from")]
     old: PathBuf,
+    #[serde(rename = "to")]
     new: PathBuf,
-    overwrite: bool,
+    on_conflict: ConflictPolicy,
+    /// How long computing `new` took.
+    #[serde(skip)]
+    planned_in: Duration,
 }
 impl Action {
-    fn new(old: PathBuf, pattern: &RenamePattern) -> Result<Action, SendableErr> {
-        let new = pattern.apply_to_file_name(&old)?;
+    pub fn source(&self) -> &Path {
+        &self.old
+    }
+
+    pub fn target(&self) -> &Path {
+        &self.new
+    }
+
+    /// What happens if the target already exists when the action is executed.
+    pub fn on_conflict(&self) -> &ConflictPolicy {
+        &self.on_conflict
+    }
+
+    /// The conflict policy is taken from the last pattern in the pipeline. The source is
+    /// resolved like the target, so actions depending on each other can be matched up.
+    fn new(
+        old: PathBuf,
+        patterns: &[(i32, RenamePattern)],
+        position: Position,
+    ) -> Result<Action, RenameError> {
+        let old = patterns::resolve_parent(&old).map_err(|e| RenameError::new(old, None, e))?;
+        let new = patterns::apply_in_sequence(patterns.iter().map(|(_, pat)| pat), &old, position)?;
         Ok(Self {
             old,
             new,
-            overwrite: pattern.options().overwrite,
+            on_conflict: patterns
+                .last()
+                .map(|(_, pat)| pat.options().on_conflict.clone())
+                .unwrap_or_default(),
+            planned_in: Duration::ZERO,
         })
     }
-    fn execute(&self) -> Result<Report, SendableErr> {
-        let mut new = self.new.clone();
-        let mut count = 0;
-        let mut overwrote = false;
-        if self.overwrite {
-            while fs::exists(&new)? {
-                count += 1;
-                new = append_to_path(new, &count.to_string());
+    /// Reports the action as planned, without executing it.
+    pub fn report(&self) -> Report {
+        if self.old == self.new {
+            Report::Unchanged {
+                path: self.old.clone(),
+                duration: self.planned_in,
             }
         } else {
-            if fs::exists(&new)? {
-                overwrote = true;
+            Report::Planned {
+                from: self.old.clone(),
+                to: self.new.clone(),
+                duration: self.planned_in,
             }
         }
-        fs::rename(&self.old, &new)?;
+    }
+    /// The action was not started because the execution was cancelled.
+    pub(crate) fn cancelled(&self) -> Report {
+        Report::Skipped {
+            path: self.old.clone(),
+            target: self.new.clone(),
+            reason: SkipReason::Cancelled,
+            duration: Duration::ZERO,
+        }
+    }
+    /// An error for this action's file.
+    pub(crate) fn error(&self, cause: impl Into<ActionError>) -> RenameError {
+        RenameError::new(self.old.clone(), Some(self.new.clone()), cause)
+    }
+    /// Performs the rename with the file currently at `from`, which differs from the
+    /// source when it had to be moved aside to break a rename cycle. With
+    /// `keep_replaced` a file replaced under [`ConflictPolicy::Overwrite`] is moved aside
+    /// rather than deleted, see [`Report::Overwrote`].
+    fn execute_from(&self, from: &Path, keep_replaced: bool) -> Report {
+        let start = Instant::now();
+        self.rename_from(from, keep_replaced, start)
+            .unwrap_or_else(|e| self.failed(e, start.elapsed()))
+    }
+    /// A failed report for this action.
+    pub(crate) fn failed(&self, cause: impl Into<ActionError>, duration: Duration) -> Report {
+        Report::Failed {
+            error: self.error(cause),
+            duration,
+        }
+    }
+    fn rename_from(
+        &self,
+        from: &Path,
+        keep_replaced: bool,
+        start: Instant,
+    ) -> Result<Report, ActionError> {
+        if from == self.new {
+            return Ok(Report::Unchanged {
+                path: self.old.clone(),
+                duration: start.elapsed(),
+            });
+        }
+        let mut new = self.new.clone();
+        let mut overwrote = false;
+        let mut displaced = None;
+        let replace = self.on_conflict == ConflictPolicy::Overwrite;
+        if replace {
+            overwrote = fs::exists(&new)?;
+        }
+        if overwrote && keep_replaced {
+            check_replaceable(from, &new)?;
+            let aside = plan::park(&new)?;
+            let copied = moves::move_path(from, &new, false).inspect_err(|_| {
+                conflict::restore_displaced(&aside, &new);
+            })?;
+            return Ok(Report::Overwrote {
+                from: self.old.clone(),
+                to: new,
+                replaced: Some(aside),
+                copied,
+                duration: start.elapsed(),
+            });
+        }
+        let copied = match moves::move_path(from, &new, replace) {
+            Ok(copied) => copied,
+            Err(e) if e.kind() != io::ErrorKind::AlreadyExists => return Err(e.into()),
+            Err(_) => match &self.on_conflict {
+                ConflictPolicy::Skip => {
+                    return Ok(Report::Skipped {
+                        path: self.old.clone(),
+                        target: new,
+                        reason: SkipReason::TargetExists,
+                        duration: start.elapsed(),
+                    });
+                }
+                ConflictPolicy::Fail => return Err(ActionError::TargetExists),
+                ConflictPolicy::AppendCounter(format) => {
+                    let (counted, copied) = conflict::rename_with_counter(from, &new, format)?;
+                    new = counted;
+                    copied
+                }
+                ConflictPolicy::KeepBoth => {
+                    let (aside, _) = conflict::rename_with_counter(
+                        &new,
+                        &new,
+                        conflict::DEFAULT_COUNTER_FORMAT,
+                    )?;
+                    let copied = moves::move_path(from, &new, false).inspect_err(|_| {
+                        conflict::restore_displaced(&aside, &new);
+                    })?;
+                    displaced = Some(aside);
+                    copied
+                }
+                ConflictPolicy::Overwrite => unreachable!(),
+            },
+        };
+        if overwrote {
+            return Ok(Report::Overwrote {
+                from: self.old.clone(),
+                to: new,
+                replaced: None,
+                copied,
+                duration: start.elapsed(),
+            });
+        }
         Ok(Report::Renamed {
             from: self.old.clone(),
-            to: new.clone(),
-            overwrote,
+            to: new,
+            displaced,
+            copied,
+            duration: start.elapsed(),
         })
     }
 }
 
-fn append_to_path(p: PathBuf, s: &str) -> PathBuf {
-    let mut p = p.into_os_string();
-    p.push(s);
-    p.into()
+/// Fails where renaming `from` over `to` would, for a target that is moved aside instead
+/// of being replaced by the rename.
+fn check_replaceable(from: &Path, to: &Path) -> io::Result<()> {
+    let from_dir = fs::symlink_metadata(from)?.is_dir();
+    let to_dir = fs::symlink_metadata(to)?.is_dir();
+    match (from_dir, to_dir) {
+        (false, true) => Err(io::ErrorKind::IsADirectory.into()),
+        (true, false) => Err(io::ErrorKind::NotADirectory.into()),
+        (true, true) if fs::read_dir(to)?.next().is_some() => {
+            Err(io::ErrorKind::DirectoryNotEmpty.into())
+        }
+        _ => Ok(()),
+    }
+}
+
+impl Display for Action {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} -> {}", self.old.display(), self.new.display())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, sync::Barrier, time::Duration};
+
+    use crate::{
+        Action, ActionGroup, ConflictPolicy, RenamePattern, SortOrder, error::ErrorCategory,
+        patterns::ActionOptions, report::Report,
+    };
+
+    #[test]
+    fn patterns_apply_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("junk_Report.txt");
+        fs::File::create(&file).unwrap();
+        let options = ActionOptions::new(true, ConflictPolicy::Fail);
+        let mut group = ActionGroup::new(0);
+        group.add_file(file);
+        group.add_pattern(RenamePattern::parse(r#"1"[^_]*$"|/cap1/"#, options.clone()).unwrap());
+        group.add_pattern(RenamePattern::parse("final_/ORIGINAL/", options.clone()).unwrap());
+        group.add_pattern(RenamePattern::parse("2024_/ORIGINAL/", options).unwrap());
+
+        let plan = group.plan();
+        assert_eq!(plan.len(), 1);
+        assert!(
+            plan.actions()[0]
+                .target()
+                .ends_with("2024_final_Report.txt")
+        );
+
+        let first = group.patterns()[0].0;
+        group.move_pattern(first, 2);
+        let plan = group.plan();
+        assert_eq!(
+            plan.actions()[0].target().file_name().unwrap(),
+            "Report.txt"
+        );
+    }
+
+    #[test]
+    fn conflict_policies() {
+        let dir = tempfile::tempdir().unwrap();
+        let run = |policy: ConflictPolicy| {
+            for name in ["a.txt", "b.txt"] {
+                fs::write(dir.path().join(name), name).unwrap();
+            }
+            let action = Action {
+                old: dir.path().join("a.txt"),
+                new: dir.path().join("b.txt"),
+                on_conflict: policy,
+                planned_in: Duration::ZERO,
+            };
+            action.execute_from(&action.old, false)
+        };
+        let read = |name: &str| fs::read_to_string(dir.path().join(name)).ok();
+
+        assert!(matches!(run(ConflictPolicy::Skip), Report::Skipped { .. }));
+        assert_eq!(read("a.txt").as_deref(), Some("a.txt"));
+
+        assert!(run(ConflictPolicy::Fail).is_failure());
+        assert_eq!(read("a.txt").as_deref(), Some("a.txt"));
+
+        assert!(matches!(
+            run(ConflictPolicy::Overwrite),
+            Report::Overwrote { .. }
+        ));
+        assert_eq!(read("b.txt").as_deref(), Some("a.txt"));
+
+        assert!(!run(ConflictPolicy::AppendCounter("_{}".to_owned())).is_failure());
+        assert_eq!(read("b_1.txt").as_deref(), Some("a.txt"));
+        assert_eq!(read("b.txt").as_deref(), Some("b.txt"));
+
+        let Report::Renamed { displaced, .. } = run(ConflictPolicy::KeepBoth) else {
+            panic!("Expected a rename");
+        };
+        assert_eq!(displaced, Some(dir.path().join("b (1).txt")));
+        assert_eq!(read("b.txt").as_deref(), Some("a.txt"));
+        assert_eq!(read("b (1).txt").as_deref(), Some("b.txt"));
+    }
+
+    #[test]
+    fn parallel_renames_never_clobber() {
+        for policy in [
+            ConflictPolicy::Fail,
+            ConflictPolicy::Skip,
+            ConflictPolicy::AppendCounter(" ({})".to_owned()),
+        ] {
+            for _ in 0..50 {
+                let dir = tempfile::tempdir().unwrap();
+                let actions = ["a", "b"].map(|name| {
+                    fs::write(dir.path().join(name), name).unwrap();
+                    Action {
+                        old: dir.path().join(name),
+                        new: dir.path().join("target"),
+                        on_conflict: policy.clone(),
+                        planned_in: Duration::ZERO,
+                    }
+                });
+                let barrier = Barrier::new(2);
+                std::thread::scope(|s| {
+                    for action in actions.iter() {
+                        s.spawn(|| {
+                            barrier.wait();
+                            let _ = action.execute_from(&action.old, false);
+                        });
+                    }
+                });
+                let mut contents: Vec<String> = fs::read_dir(dir.path())
+                    .unwrap()
+                    .map(|entry| fs::read_to_string(entry.unwrap().path()).unwrap())
+                    .collect();
+                contents.sort();
+                assert_eq!(contents, ["a", "b"], "{policy}");
+            }
+        }
+    }
+
+    #[test]
+    fn errors_name_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let missing = dir.path().join("missing.txt");
+        let dot_dot = dir.path().join("..");
+        let mut group = ActionGroup::new(0);
+        group.add_file(missing.clone());
+        group.add_file(dot_dot.clone());
+        group.add_pattern(RenamePattern::parse("new", ActionOptions::default()).unwrap());
+
+        let mut results = group.execute();
+        results.sort_by_key(|r| r.path().to_owned());
+        let errors: Vec<_> = results.iter().map(|r| r.error().unwrap()).collect();
+        assert_eq!(errors[0].path(), dot_dot);
+        assert_eq!(errors[0].category(), ErrorCategory::InvalidName);
+        assert_eq!(errors[1].path(), missing);
+        assert_eq!(errors[1].category(), ErrorCategory::NotFound);
+        assert!(errors[1].to_string().contains("missing.txt"));
+    }
+
+    #[test]
+    fn runs_follow_sort_order() {
+        let names = ["img10.jpg", "img2.jpg", "img1.jpg", "img3.jpg"];
+        let sources = |reports: &[Report]| -> Vec<String> {
+            reports
+                .iter()
+                .map(|r| r.path().file_name().unwrap().to_string_lossy().into_owned())
+                .collect()
+        };
+        let mut runs = Vec::new();
+        for _ in 0..5 {
+            let dir = tempfile::tempdir().unwrap();
+            let mut group = ActionGroup::new(0);
+            for name in names {
+                fs::write(dir.path().join(name), name).unwrap();
+                group.add_file(dir.path().join(name));
+            }
+            group.set_sort_order(SortOrder::Natural);
+            group.add_pattern(
+                RenamePattern::parse("new_/ORIGINAL/", ActionOptions::default()).unwrap(),
+            );
+            let planned = sources(&group.plan().preview());
+            assert_eq!(planned, ["img1.jpg", "img2.jpg", "img3.jpg", "img10.jpg"]);
+            let executed = sources(&group.execute());
+            assert_eq!(executed, planned);
+            runs.push(executed);
+        }
+        assert!(runs.windows(2).all(|w| w[0] == w[1]));
+    }
+
+    #[test]
+    fn counters_follow_order_and_reset_per_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut group = ActionGroup::new(0);
+        for sub in ["b", "a"] {
+            fs::create_dir(dir.path().join(sub)).unwrap();
+            for name in ["y.txt", "x.txt"] {
+                let path = dir.path().join(sub).join(name);
+                fs::write(&path, name).unwrap();
+                group.add_file(path);
+            }
+        }
+        group.set_sort_order(SortOrder::Name);
+        let targets = |group: &ActionGroup| -> Vec<String> {
+            group
+                .plan()
+                .actions()
+                .iter()
+                .map(|a| {
+                    let rel = a.target().strip_prefix(dir.path().canonicalize().unwrap());
+                    rel.unwrap().to_string_lossy().into_owned()
+                })
+                .collect()
+        };
+
+        group.add_pattern(
+            RenamePattern::parse("/N:start=0,step=5,pad=2/", ActionOptions::default()).unwrap(),
+        );
+        // Name order ties on the file name are broken by the full path.
+        assert_eq!(targets(&group), ["a/00", "b/05", "a/10", "b/15"]);
+
+        group.set_patterns(Vec::new());
+        group.add_pattern(
+            RenamePattern::parse("/N:reset=dir/_/ORIGINAL/", ActionOptions::default()).unwrap(),
+        );
+        assert_eq!(
+            targets(&group),
+            ["a/1_x.txt", "b/1_x.txt", "a/2_y.txt", "b/2_y.txt"]
+        );
+    }
 }
@@ -0,0 +1,294 @@
+use std::{
+    ffi::OsString,
+    fs::{self, File, FileTimes, Metadata, OpenOptions},
+    io,
+    path::{Path, PathBuf},
+};
+
+use crate::noclobber;
+
+/// Moves `from` to `to`, replacing `to` only if `replace` is set. Renaming across
+/// filesystems fails with EXDEV, in that case the file is copied instead and the source
+/// removed once the copy is synced. Returns true if the copy fallback was used.
+pub(crate) fn move_path(from: &Path, to: &Path, replace: bool) -> io::Result<bool> {
+    let res = if replace {
+        fs::rename(from, to)
+    } else {
+        noclobber::rename(from, to)
+    };
+    match res {
+        Ok(()) => Ok(false),
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            log::info!("{from:?} and {to:?} are on different filesystems, copying instead");
+            copy_then_remove(from, to, replace)?;
+            Ok(true)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Copies `from` to a temporary name next to `to` and only puts it in place once the copy
+/// is complete and synced, so a failed copy never costs the existing target. The source is
+/// removed last, after the parent directory of `to` is synced.
+fn copy_then_remove(from: &Path, to: &Path, replace: bool) -> io::Result<()> {
+    let metadata = fs::symlink_metadata(from)?;
+    let tmp = temp_sibling(to)?;
+    if let Err(e) = copy_entry(from, &tmp, &metadata) {
+        if let Err(cleanup_err) = remove_entry(&tmp)
+            && cleanup_err.kind() != io::ErrorKind::NotFound
+        {
+            log::error!("Could not remove partial copy {tmp:?}!: {cleanup_err}");
+        }
+        return Err(e);
+    }
+    if let Err(e) = install(&tmp, to, replace, metadata.is_dir()) {
+        if let Err(cleanup_err) = remove_entry(&tmp) {
+            log::error!("Could not remove copy {tmp:?}!: {cleanup_err}");
+        }
+        return Err(e);
+    }
+    sync_parent(to)?;
+    remove_entry(from)
+}
+
+/// Renames the finished copy `tmp` to `to`. A rename can't replace a directory, or a file
+/// with a directory, so such a target is moved aside first and only removed once the copy
+/// is in its place.
+fn install(tmp: &Path, to: &Path, replace: bool, is_dir: bool) -> io::Result<()> {
+    if !replace {
+        return noclobber::rename(tmp, to);
+    }
+    let existing = match fs::symlink_metadata(to) {
+        Ok(existing) => existing,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return fs::rename(tmp, to),
+        Err(e) => return Err(e),
+    };
+    if !existing.is_dir() && !is_dir {
+        return fs::rename(tmp, to);
+    }
+    let aside = temp_sibling(to)?;
+    fs::rename(to, &aside)?;
+    if let Err(e) = fs::rename(tmp, to) {
+        if let Err(restore_err) = fs::rename(&aside, to) {
+            log::error!("Could not restore {to:?} from {aside:?}!: {restore_err}");
+        }
+        return Err(e);
+    }
+    if let Err(e) = remove_entry(&aside) {
+        log::error!("Could not remove replaced {aside:?}!: {e}");
+    }
+    Ok(())
+}
+
+/// An unused name in the same directory as `p`, so renaming to and from it stays on one
+/// filesystem.
+fn temp_sibling(p: &Path) -> io::Result<PathBuf> {
+    let name = p.file_name().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{p:?} has no file name"),
+        )
+    })?;
+    let mut tmp_name = OsString::from(".");
+    tmp_name.push(name);
+    tmp_name.push(format!(
+        ".{}-{:08x}.tmp",
+        std::process::id(),
+        rand::random::<u32>()
+    ));
+    Ok(p.with_file_name(tmp_name))
+}
+
+/// Syncs the directory containing `p`, so the rename to `p` survives a crash.
+#[cfg(unix)]
+fn sync_parent(p: &Path) -> io::Result<()> {
+    match p.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => File::open(parent)?.sync_all(),
+        _ => File::open(".")?.sync_all(),
+    }
+}
+
+#[cfg(not(unix))]
+fn sync_parent(_p: &Path) -> io::Result<()> {
+    // Directories can't be opened to be synced on other platforms.
+    Ok(())
+}
+
+/// Copies a file, directory or symlink to `to`, which must not exist, keeping its
+/// permissions and timestamps.
+fn copy_entry(from: &Path, to: &Path, metadata: &Metadata) -> io::Result<()> {
+    let file_type = metadata.file_type();
+    if file_type.is_symlink() {
+        return copy_symlink(from, to);
+    }
+    let dest = if file_type.is_dir() {
+        fs::create_dir(to)?;
+        for entry in fs::read_dir(from)? {
+            let entry = entry?;
+            copy_entry(
+                &entry.path(),
+                &to.join(entry.file_name()),
+                &entry.metadata()?,
+            )?;
+        }
+        File::open(to)?
+    } else {
+        let mut src = File::open(from)?;
+        let mut dest = OpenOptions::new().write(true).create_new(true).open(to)?;
+        io::copy(&mut src, &mut dest)?;
+        dest
+    };
+    dest.set_times(
+        FileTimes::new()
+            .set_accessed(metadata.accessed()?)
+            .set_modified(metadata.modified()?),
+    )?;
+    dest.set_permissions(metadata.permissions())?;
+    dest.sync_all()
+}
+
+#[cfg(unix)]
+fn copy_symlink(from: &Path, to: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(fs::read_link(from)?, to)
+}
+
+#[cfg(not(unix))]
+fn copy_symlink(from: &Path, _to: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("Cannot copy symlink {from:?} across filesystems"),
+    ))
+}
+
+fn remove_entry(p: &Path) -> io::Result<()> {
+    if fs::symlink_metadata(p)?.is_dir() {
+        fs::remove_dir_all(p)
+    } else {
+        fs::remove_file(p)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        fs::{self, File, FileTimes},
+        io,
+        path::Path,
+        time::{Duration, SystemTime},
+    };
+
+    use super::{copy_then_remove, move_path};
+
+    fn write_old_file(p: &Path, contents: &str) -> SystemTime {
+        fs::write(p, contents).unwrap();
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        File::options()
+            .write(true)
+            .open(p)
+            .unwrap()
+            .set_times(FileTimes::new().set_modified(modified))
+            .unwrap();
+        modified
+    }
+
+    #[test]
+    fn copy_keeps_contents_and_times() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src");
+        fs::create_dir(&src).unwrap();
+        let modified = write_old_file(&src.join("inner.txt"), "inner");
+        let mut perms = fs::metadata(src.join("inner.txt")).unwrap().permissions();
+        perms.set_readonly(true);
+        fs::set_permissions(src.join("inner.txt"), perms).unwrap();
+
+        let dest = dir.path().join("dest");
+        copy_then_remove(&src, &dest, false).unwrap();
+        assert!(!src.exists());
+        let copied = dest.join("inner.txt");
+        assert_eq!(fs::read_to_string(&copied).unwrap(), "inner");
+        let metadata = fs::metadata(&copied).unwrap();
+        assert_eq!(metadata.modified().unwrap(), modified);
+        assert!(metadata.permissions().readonly());
+
+        fs::write(dir.path().join("other"), "other").unwrap();
+        let err = copy_then_remove(&dest, &dir.path().join("other"), false).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert!(dest.exists());
+        assert_eq!(
+            fs::read_to_string(dir.path().join("other")).unwrap(),
+            "other"
+        );
+    }
+
+    #[test]
+    fn copy_replaces_non_empty_directories() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src");
+        fs::create_dir(&src).unwrap();
+        fs::write(src.join("new.txt"), "new").unwrap();
+        let dest = dir.path().join("dest");
+        fs::create_dir(&dest).unwrap();
+        fs::write(dest.join("old.txt"), "old").unwrap();
+
+        copy_then_remove(&src, &dest, true).unwrap();
+        assert!(!src.exists());
+        assert_eq!(fs::read_to_string(dest.join("new.txt")).unwrap(), "new");
+        assert!(!dest.join("old.txt").exists());
+        // No temporary copies are left behind.
+        let names: Vec<_> = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        assert_eq!(names, ["dest"]);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn failed_copy_keeps_target() {
+        use std::os::unix::net::UnixListener;
+
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src");
+        fs::create_dir(&src).unwrap();
+        fs::write(src.join("a.txt"), "a").unwrap();
+        // A socket can't be opened to be copied.
+        let _listener = UnixListener::bind(src.join("socket")).unwrap();
+        let dest = dir.path().join("dest");
+        fs::write(&dest, "existing").unwrap();
+
+        assert!(copy_then_remove(&src, &dest, true).is_err());
+        assert_eq!(fs::read_to_string(&dest).unwrap(), "existing");
+        assert!(src.join("a.txt").exists());
+        let mut names: Vec<_> = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["dest", "src"]);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn move_between_tmpfs_and_disk() {
+        use std::os::unix::fs::MetadataExt;
+
+        let Ok(tmpfs) = tempfile::tempdir_in("/dev/shm") else {
+            return;
+        };
+        let disk = tempfile::tempdir_in(env!("CARGO_MANIFEST_DIR")).unwrap();
+        let device = |p: &Path| fs::metadata(p).unwrap().dev();
+        let crosses = device(tmpfs.path()) != device(disk.path());
+        let src = tmpfs.path().join("a.txt");
+        let modified = write_old_file(&src, "a");
+
+        let dest = disk.path().join("a.txt");
+        assert_eq!(move_path(&src, &dest, false).unwrap(), crosses);
+        assert!(!src.exists());
+        assert_eq!(fs::read_to_string(&dest).unwrap(), "a");
+        assert_eq!(fs::metadata(&dest).unwrap().modified().unwrap(), modified);
+
+        let back = tmpfs.path().join("b.txt");
+        assert_eq!(move_path(&dest, &back, true).unwrap(), crosses);
+        assert_eq!(fs::read_to_string(&back).unwrap(), "a");
+    }
+}
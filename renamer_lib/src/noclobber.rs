@@ -0,0 +1,101 @@
+use std::{fs, io, path::Path};
+
+/// Renames `from` to `to`, failing with [`io::ErrorKind::AlreadyExists`] instead of
+/// replacing `to` if it exists. The check and the rename happen atomically, so a file
+/// created at `to` by another process or another action in the same batch is never
+/// clobbered.
+pub(crate) fn rename(from: &Path, to: &Path) -> io::Result<()> {
+    #[cfg(target_os = "linux")]
+    match renameat2_noreplace(from, to) {
+        Err(e)
+            if matches!(
+                e.raw_os_error(),
+                Some(libc::EINVAL | libc::ENOSYS | libc::EOPNOTSUPP)
+            ) =>
+        {
+            log::debug!("RENAME_NOREPLACE unsupported for {from:?}, falling back to link: {e}");
+        }
+        res => return res,
+    }
+    link_and_unlink(from, to)
+}
+
+#[cfg(target_os = "linux")]
+fn renameat2_noreplace(from: &Path, to: &Path) -> io::Result<()> {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+    let from = CString::new(from.as_os_str().as_bytes())?;
+    let to = CString::new(to.as_os_str().as_bytes())?;
+    // SAFETY: both paths are valid nul terminated strings that outlive the call.
+    let ret = unsafe {
+        libc::renameat2(
+            libc::AT_FDCWD,
+            from.as_ptr(),
+            libc::AT_FDCWD,
+            to.as_ptr(),
+            libc::RENAME_NOREPLACE,
+        )
+    };
+    if ret == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// Creating a hard link fails if the target exists, so linking then removing the
+/// source gives the same guarantee for files. Directories can't be hard linked, and
+/// some filesystems don't support links at all, those fall back to a plain rename
+/// after checking the target, which is not atomic.
+fn link_and_unlink(from: &Path, to: &Path) -> io::Result<()> {
+    match fs::hard_link(from, to) {
+        Ok(()) => fs::remove_file(from),
+        Err(e)
+            if matches!(
+                e.kind(),
+                io::ErrorKind::AlreadyExists | io::ErrorKind::CrossesDevices
+            ) =>
+        {
+            Err(e)
+        }
+        Err(e) => {
+            log::debug!("Could not link {from:?} to {to:?}, renaming non-atomically: {e}");
+            if fs::exists(to)? {
+                return Err(io::ErrorKind::AlreadyExists.into());
+            }
+            fs::rename(from, to)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, io};
+
+    use super::{link_and_unlink, rename};
+
+    #[test]
+    fn refuses_existing_target() {
+        let dir = tempfile::tempdir().unwrap();
+        let (a, b, c) = (
+            dir.path().join("a"),
+            dir.path().join("b"),
+            dir.path().join("c"),
+        );
+        fs::write(&a, "a").unwrap();
+        fs::write(&b, "b").unwrap();
+
+        for rename_fn in [rename, link_and_unlink] {
+            let err = rename_fn(&a, &b).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+            assert_eq!(fs::read_to_string(&b).unwrap(), "b");
+        }
+
+        rename(&a, &c).unwrap();
+        assert!(!a.exists());
+        assert_eq!(fs::read_to_string(&c).unwrap(), "a");
+        link_and_unlink(&c, &a).unwrap();
+        assert!(!c.exists());
+        assert_eq!(fs::read_to_string(&a).unwrap(), "a");
+    }
+}
@@ -0,0 +1,265 @@
+use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use serde::Serialize;
+use thiserror::Error;
+
+/// The order an [`crate::ActionGroup`]'s files are planned and reported in. Ties are
+/// broken by the full path and then by insertion order, so the order is always the same
+/// for the same files.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SortOrder {
+    /// The order the files were added to the group.
+    #[default]
+    Insertion,
+    /// By file name, comparing characters.
+    Name,
+    /// By file name, comparing runs of digits by their value so `2` comes before `10`.
+    Natural,
+    /// Oldest modification time first. Files whose time can't be read go last.
+    Modified,
+    /// Smallest first. Files whose size can't be read go last.
+    Size,
+    /// By extension, then naturally by name.
+    Extension,
+}
+
+#[derive(Error, Debug)]
+#[error(
+    "Unknown sort order {0:?}, expected one of insertion, name, natural, mtime, size, extension"
+)]
+pub struct ParseSortOrderError(String);
+
+impl FromStr for SortOrder {
+    type Err = ParseSortOrderError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "insertion" => Ok(Self::Insertion),
+            "name" => Ok(Self::Name),
+            "natural" => Ok(Self::Natural),
+            "mtime" => Ok(Self::Modified),
+            "size" => Ok(Self::Size),
+            "extension" => Ok(Self::Extension),
+            _ => Err(ParseSortOrderError(s.to_owned())),
+        }
+    }
+}
+
+impl Display for SortOrder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SortOrder::Insertion => write!(f, "insertion"),
+            SortOrder::Name => write!(f, "name"),
+            SortOrder::Natural => write!(f, "natural"),
+            SortOrder::Modified => write!(f, "mtime"),
+            SortOrder::Size => write!(f, "size"),
+            SortOrder::Extension => write!(f, "extension"),
+        }
+    }
+}
+
+impl Serialize for SortOrder {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl SortOrder {
+    /// Every order, in the order they are listed above.
+    pub const ALL: [SortOrder; 6] = [
+        SortOrder::Insertion,
+        SortOrder::Name,
+        SortOrder::Natural,
+        SortOrder::Modified,
+        SortOrder::Size,
+        SortOrder::Extension,
+    ];
+
+    /// Sorts `files`, keyed by their insertion id.
+    pub(crate) fn sort<'a>(&self, files: &'a HashMap<i32, PathBuf>) -> Vec<(i32, &'a Path)> {
+        let mut sorted: Vec<(i32, &Path)> = files
+            .iter()
+            .map(|(id, path)| (*id, path.as_path()))
+            .collect();
+        let tie_break = |a: &(i32, &Path), b: &(i32, &Path)| a.1.cmp(b.1).then(a.0.cmp(&b.0));
+        match self {
+            SortOrder::Insertion => sorted.sort_by_key(|(id, _)| *id),
+            SortOrder::Name => {
+                sorted.sort_by(|a, b| file_name(a.1).cmp(&file_name(b.1)).then(tie_break(a, b)))
+            }
+            SortOrder::Natural => sorted.sort_by(|a, b| {
+                natural_cmp(&file_name(a.1), &file_name(b.1)).then(tie_break(a, b))
+            }),
+            SortOrder::Modified => sort_by_metadata(&mut sorted, |m| m.modified().ok(), tie_break),
+            SortOrder::Size => sort_by_metadata(&mut sorted, |m| Some(m.len()), tie_break),
+            SortOrder::Extension => sorted.sort_by(|a, b| {
+                extension(a.1)
+                    .cmp(&extension(b.1))
+                    .then_with(|| natural_cmp(&file_name(a.1), &file_name(b.1)))
+                    .then(tie_break(a, b))
+            }),
+        }
+        sorted
+    }
+}
+
+/// Reads each file's metadata once, files without a key go last.
+fn sort_by_metadata<K: Ord>(
+    files: &mut [(i32, &Path)],
+    key: impl Fn(&fs::Metadata) -> Option<K>,
+    tie_break: impl Fn(&(i32, &Path), &(i32, &Path)) -> Ordering,
+) {
+    let keys: HashMap<i32, Option<K>> = files
+        .iter()
+        .map(|(id, path)| (*id, fs::metadata(path).ok().and_then(|m| key(&m))))
+        .collect();
+    files.sort_by(|a, b| {
+        let order = match (&keys[&a.0], &keys[&b.0]) {
+            (Some(x), Some(y)) => x.cmp(y),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => Ordering::Equal,
+        };
+        order.then(tie_break(a, b))
+    });
+}
+
+fn file_name(p: &Path) -> std::borrow::Cow<'_, str> {
+    p.file_name().unwrap_or(p.as_os_str()).to_string_lossy()
+}
+
+fn extension(p: &Path) -> Option<String> {
+    p.extension().map(|e| e.to_string_lossy().to_lowercase())
+}
+
+/// Compares strings with runs of ASCII digits compared by value, e.g. `img2` < `img10`.
+/// Equal values with a different number of leading zeros put the shorter run first.
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a, b);
+    loop {
+        let (Some(ca), Some(cb)) = (a.chars().next(), b.chars().next()) else {
+            return a.len().cmp(&b.len());
+        };
+        let order = if ca.is_ascii_digit() && cb.is_ascii_digit() {
+            let (da, rest_a) = split_digits(a);
+            let (db, rest_b) = split_digits(b);
+            (a, b) = (rest_a, rest_b);
+            let (ta, tb) = (da.trim_start_matches('0'), db.trim_start_matches('0'));
+            ta.len()
+                .cmp(&tb.len())
+                .then_with(|| ta.cmp(tb))
+                .then(da.len().cmp(&db.len()))
+        } else {
+            (a, b) = (&a[ca.len_utf8()..], &b[cb.len_utf8()..]);
+            ca.cmp(&cb)
+        };
+        if order != Ordering::Equal {
+            return order;
+        }
+    }
+}
+
+fn split_digits(s: &str) -> (&str, &str) {
+    let end = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    s.split_at(end)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        cmp::Ordering,
+        collections::HashMap,
+        fs::{self, File, FileTimes},
+        path::{Path, PathBuf},
+        time::{Duration, SystemTime},
+    };
+
+    use super::{SortOrder, natural_cmp};
+
+    fn set_modified(p: &Path, secs: u64) {
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+        File::options()
+            .write(true)
+            .open(p)
+            .unwrap()
+            .set_times(FileTimes::new().set_modified(time))
+            .unwrap();
+    }
+
+    #[test]
+    fn natural_order() {
+        let mut names = vec![
+            "img10.png",
+            "img2.png",
+            "img02.png",
+            "Img1.png",
+            "img1a.png",
+        ];
+        names.sort_by(|a, b| natural_cmp(a, b));
+        assert_eq!(
+            names,
+            [
+                "Img1.png",
+                "img1a.png",
+                "img2.png",
+                "img02.png",
+                "img10.png"
+            ]
+        );
+        assert_eq!(natural_cmp("a", "a"), Ordering::Equal);
+        assert_eq!(natural_cmp("a", "a1"), Ordering::Less);
+    }
+
+    #[test]
+    fn sort_orders() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut files = HashMap::new();
+        for (id, (name, size, mtime)) in
+            [("b10.txt", 3, 300), ("b2.jpg", 1, 100), ("a.png", 2, 200)]
+                .into_iter()
+                .enumerate()
+        {
+            let path = dir.path().join(name);
+            fs::write(&path, "x".repeat(size)).unwrap();
+            set_modified(&path, mtime);
+            files.insert(id as i32, path);
+        }
+        files.insert(3, PathBuf::from("/missing/c"));
+
+        let names = |order: SortOrder| -> Vec<String> {
+            order
+                .sort(&files)
+                .into_iter()
+                .map(|(_, p)| p.file_name().unwrap().to_string_lossy().into_owned())
+                .collect()
+        };
+        assert_eq!(
+            names(SortOrder::Insertion),
+            ["b10.txt", "b2.jpg", "a.png", "c"]
+        );
+        assert_eq!(names(SortOrder::Name), ["a.png", "b10.txt", "b2.jpg", "c"]);
+        assert_eq!(
+            names(SortOrder::Natural),
+            ["a.png", "b2.jpg", "b10.txt", "c"]
+        );
+        assert_eq!(
+            names(SortOrder::Modified),
+            ["b2.jpg", "a.png", "b10.txt", "c"]
+        );
+        assert_eq!(names(SortOrder::Size), ["b2.jpg", "a.png", "b10.txt", "c"]);
+        assert_eq!(
+            names(SortOrder::Extension),
+            ["c", "b2.jpg", "a.png", "b10.txt"]
+        );
+        for order in SortOrder::ALL {
+            assert_eq!(order.to_string().parse::<SortOrder>().unwrap(), order);
+        }
+    }
+}
@@ -0,0 +1,205 @@
+use std::{fs::File, io, path::Path};
+
+use symphonia::core::{
+    errors::Error as SymphoniaError,
+    formats::FormatOptions,
+    io::MediaSourceStream,
+    meta::{MetadataOptions, StandardTagKey, Tag},
+    probe::Hint,
+};
+
+use crate::error::ActionError;
+
+/// A tag read from an audio file's ID3, Vorbis comment or MP4 metadata, e.g.
+/// `/TAG:track:pad=2/`.
+#[derive(PartialEq, Debug, Clone)]
+pub(super) struct AudioTag {
+    field: AudioField,
+    /// Zero pad numeric values to this many digits.
+    pad: usize,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+enum AudioField {
+    Artist,
+    AlbumArtist,
+    Album,
+    Title,
+    Track,
+    TrackTotal,
+    Disc,
+    DiscTotal,
+    Date,
+    /// The first four digits of the date.
+    Year,
+    Genre,
+    Composer,
+    Comment,
+}
+
+const FIELDS: [(&str, AudioField); 13] = [
+    ("artist", AudioField::Artist),
+    ("album_artist", AudioField::AlbumArtist),
+    ("album", AudioField::Album),
+    ("title", AudioField::Title),
+    ("track", AudioField::Track),
+    ("track_total", AudioField::TrackTotal),
+    ("disc", AudioField::Disc),
+    ("disc_total", AudioField::DiscTotal),
+    ("date", AudioField::Date),
+    ("year", AudioField::Year),
+    ("genre", AudioField::Genre),
+    ("composer", AudioField::Composer),
+    ("comment", AudioField::Comment),
+];
+
+impl AudioTag {
+    /// The tag called `name`, if there is one.
+    pub(super) fn new(name: &str, pad: usize) -> Option<Self> {
+        let (_, field) = FIELDS.iter().find(|(n, _)| *n == name)?;
+        Some(Self { field: *field, pad })
+    }
+
+    /// The names of every tag, for error messages.
+    pub(super) fn names() -> impl Iterator<Item = &'static str> {
+        FIELDS.iter().map(|(name, _)| *name)
+    }
+
+    fn name(&self) -> &'static str {
+        FIELDS
+            .iter()
+            .find(|(_, f)| *f == self.field)
+            .map(|(name, _)| *name)
+            .expect("Every field is named")
+    }
+
+    /// Reads the tag from `fpath`. A file that isn't audio or doesn't have the tag fails
+    /// with [`ActionError::MissingMetadata`].
+    pub(super) fn read(&self, fpath: &Path) -> Result<String, ActionError> {
+        let missing = || ActionError::MissingMetadata(format!("{} tag", self.name()));
+        let tags = read_tags(fpath)?;
+        let get = |key: StandardTagKey| {
+            tags.iter()
+                .rev()
+                .find(|t| t.std_key == Some(key))
+                .map(|t| t.value.to_string())
+                .filter(|v| !v.trim().is_empty())
+        };
+        // Track and disc numbers are often written as "3/12".
+        let number = |key| get(key).map(|v| v.split('/').next().unwrap_or_default().to_owned());
+        let total = |total_key, number_key| {
+            get(total_key).or_else(|| {
+                get(number_key).and_then(|v| v.split_once('/').map(|(_, t)| t.to_owned()))
+            })
+        };
+        let value = match self.field {
+            AudioField::Artist => get(StandardTagKey::Artist),
+            AudioField::AlbumArtist => get(StandardTagKey::AlbumArtist),
+            AudioField::Album => get(StandardTagKey::Album),
+            AudioField::Title => get(StandardTagKey::TrackTitle),
+            AudioField::Track => number(StandardTagKey::TrackNumber),
+            AudioField::TrackTotal => {
+                total(StandardTagKey::TrackTotal, StandardTagKey::TrackNumber)
+            }
+            AudioField::Disc => number(StandardTagKey::DiscNumber),
+            AudioField::DiscTotal => total(StandardTagKey::DiscTotal, StandardTagKey::DiscNumber),
+            AudioField::Date => get(StandardTagKey::Date),
+            AudioField::Year => get(StandardTagKey::Date).and_then(|d| {
+                d.get(..4)
+                    .filter(|y| y.bytes().all(|b| b.is_ascii_digit()))
+                    .map(str::to_owned)
+            }),
+            AudioField::Genre => get(StandardTagKey::Genre),
+            AudioField::Composer => get(StandardTagKey::Composer),
+            AudioField::Comment => get(StandardTagKey::Comment),
+        }
+        .ok_or_else(missing)?;
+        let value = super::sanitize(&value);
+        if self.pad > 0 && value.bytes().all(|b| b.is_ascii_digit()) {
+            return Ok(format!("{value:0>pad$}", pad = self.pad));
+        }
+        Ok(value)
+    }
+}
+
+/// Every tag in the file, those from the container after any read before it, e.g. an ID3
+/// tag at the start of an MP3. A file that can't be read as audio has no tags.
+fn read_tags(fpath: &Path) -> Result<Vec<Tag>, ActionError> {
+    let source = MediaSourceStream::new(Box::new(File::open(fpath)?), Default::default());
+    let mut hint = Hint::new();
+    if let Some(ext) = fpath.extension().and_then(|e| e.to_str()) {
+        hint.with_extension(ext);
+    }
+    let mut probed = match symphonia::default::get_probe().format(
+        &hint,
+        source,
+        &FormatOptions::default(),
+        &MetadataOptions::default(),
+    ) {
+        Ok(probed) => probed,
+        Err(SymphoniaError::IoError(e)) if e.kind() != io::ErrorKind::UnexpectedEof => {
+            return Err(e.into());
+        }
+        Err(_) => return Ok(Vec::new()),
+    };
+    let mut tags = Vec::new();
+    if let Some(mut metadata) = probed.metadata.get()
+        && let Some(revision) = metadata.skip_to_latest()
+    {
+        tags.extend_from_slice(revision.tags());
+    }
+    if let Some(revision) = probed.format.metadata().skip_to_latest() {
+        tags.extend_from_slice(revision.tags());
+    }
+    Ok(tags)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, path::Path};
+
+    use super::AudioTag;
+    use crate::error::ActionError;
+
+    fn fixture(name: &str) -> String {
+        format!("{}/fixtures/{name}", env!("CARGO_MANIFEST_DIR"))
+    }
+
+    fn read(file: &str, name: &str, pad: usize) -> Result<String, ActionError> {
+        AudioTag::new(name, pad)
+            .unwrap()
+            .read(Path::new(&fixture(file)))
+    }
+
+    #[test]
+    fn reads_id3() {
+        assert_eq!(read("tags.mp3", "artist", 0).unwrap(), "AC_DC");
+        assert_eq!(read("tags.mp3", "album", 0).unwrap(), "Back in Black");
+        assert_eq!(read("tags.mp3", "title", 0).unwrap(), "Hells Bells");
+        assert_eq!(read("tags.mp3", "track", 2).unwrap(), "01");
+        assert_eq!(read("tags.mp3", "track_total", 0).unwrap(), "10");
+        assert!(matches!(
+            read("tags.mp3", "genre", 0),
+            Err(ActionError::MissingMetadata(_))
+        ));
+    }
+
+    #[test]
+    fn reads_vorbis_comments() {
+        assert_eq!(read("tags.flac", "artist", 0).unwrap(), "Nina Simone");
+        assert_eq!(read("tags.flac", "title", 0).unwrap(), "Sinnerman");
+        assert_eq!(read("tags.flac", "track", 3).unwrap(), "009");
+        assert_eq!(read("tags.flac", "year", 0).unwrap(), "1965");
+        // Padding only applies to numbers.
+        assert_eq!(read("tags.flac", "album", 20).unwrap(), "Pastel Blues");
+    }
+
+    #[test]
+    fn files_without_tags() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("notes.mp3");
+        fs::write(&file, "not audio").unwrap();
+        let err = AudioTag::new("artist", 0).unwrap().read(&file).unwrap_err();
+        assert_eq!(err.to_string(), "The file has no artist tag");
+    }
+}
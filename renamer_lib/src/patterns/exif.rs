@@ -0,0 +1,126 @@
+use std::{
+    fmt::Write,
+    fs::File,
+    io::{self, BufReader},
+    path::Path,
+};
+
+use chrono::NaiveDate;
+use exif::{Exif, In, Value};
+
+use crate::error::ActionError;
+
+/// The tags holding a date and time, which can be given a strftime format.
+const DATE_TAGS: [&str; 3] = ["DateTime", "DateTimeOriginal", "DateTimeDigitized"];
+
+/// An EXIF tag of the primary image, looked up by the name the EXIF standard gives it,
+/// e.g. `Model` or `DateTimeOriginal`.
+#[derive(PartialEq, Debug, Clone)]
+pub(super) struct ExifInsert {
+    tag: String,
+    /// A strftime format for date tags, checked by the parser.
+    format: Option<String>,
+}
+
+impl ExifInsert {
+    pub(super) fn new(tag: String, format: Option<String>) -> Self {
+        Self { tag, format }
+    }
+
+    pub(super) fn is_date(tag: &str) -> bool {
+        DATE_TAGS.contains(&tag)
+    }
+
+    /// Reads the tag from `fpath`. A file without EXIF data or without the tag fails with
+    /// [`ActionError::MissingMetadata`].
+    pub(super) fn read(&self, fpath: &Path) -> Result<String, ActionError> {
+        let missing = || ActionError::MissingMetadata(format!("EXIF {}", self.tag));
+        let exif = read_exif(fpath)?.ok_or_else(missing)?;
+        let field = exif
+            .fields()
+            .filter(|f| f.tag.to_string() == self.tag)
+            .min_by_key(|f| f.ifd_num != In::PRIMARY)
+            .ok_or_else(missing)?;
+        let text = match &field.value {
+            Value::Ascii(strings) if Self::is_date(&self.tag) => {
+                let date = strings
+                    .first()
+                    .and_then(|s| exif::DateTime::from_ascii(s).ok())
+                    .and_then(|dt| {
+                        NaiveDate::from_ymd_opt(dt.year.into(), dt.month.into(), dt.day.into())?
+                            .and_hms_opt(dt.hour.into(), dt.minute.into(), dt.second.into())
+                    })
+                    .ok_or_else(missing)?;
+                let format = self.format.as_deref().unwrap_or("%Y-%m-%d_%H-%M-%S");
+                let mut out = String::new();
+                write!(out, "{}", date.format(format))
+                    .map_err(|_| ActionError::Pattern(format!("Could not format date {date}")))?;
+                out
+            }
+            Value::Ascii(strings) => strings
+                .iter()
+                .map(|s| String::from_utf8_lossy(s))
+                .collect::<Vec<_>>()
+                .join(" "),
+            _ => field.display_value().with_unit(&exif).to_string(),
+        };
+        let text = super::sanitize(&text);
+        if text.is_empty() {
+            return Err(missing());
+        }
+        Ok(text)
+    }
+}
+
+/// The file's EXIF data, or `None` if it isn't an image with any.
+fn read_exif(fpath: &Path) -> Result<Option<Exif>, ActionError> {
+    let mut reader = BufReader::new(File::open(fpath)?);
+    match exif::Reader::new().read_from_container(&mut reader) {
+        Ok(exif) => Ok(Some(exif)),
+        Err(exif::Error::Io(e)) if e.kind() != io::ErrorKind::UnexpectedEof => Err(e.into()),
+        Err(_) => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, path::Path};
+
+    use super::ExifInsert;
+    use crate::error::{ActionError, ErrorCategory};
+
+    const FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/exif.jpg");
+
+    #[test]
+    fn reads_tags() {
+        let read = |tag: &str, format: Option<&str>| {
+            ExifInsert::new(tag.to_owned(), format.map(str::to_owned)).read(Path::new(FIXTURE))
+        };
+        assert_eq!(read("Make", None).unwrap(), "Acme");
+        assert_eq!(read("Model", None).unwrap(), "Acme X_100");
+        assert_eq!(read("LensModel", None).unwrap(), "Acme 35mm F1.8");
+        assert_eq!(
+            read("DateTimeOriginal", None).unwrap(),
+            "2021-06-05_14-30-09"
+        );
+        assert_eq!(read("DateTimeOriginal", Some("%H:%M")).unwrap(), "14_30");
+        assert_eq!(
+            read("DateTimeOriginal", Some("%Y%m%d_%H%M%S")).unwrap(),
+            "20210605_143009"
+        );
+        let missing = read("Artist", None).unwrap_err();
+        assert!(matches!(missing, ActionError::MissingMetadata(_)));
+        assert_eq!(missing.category(), ErrorCategory::Unsupported);
+    }
+
+    #[test]
+    fn files_without_exif() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("notes.jpg");
+        fs::write(&file, "not an image").unwrap();
+        let err = ExifInsert::new("Model".to_owned(), None)
+            .read(&file)
+            .unwrap_err();
+        assert_eq!(err.to_string(), "The file has no EXIF Model");
+    }
+}
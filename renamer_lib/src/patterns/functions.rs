@@ -0,0 +1,343 @@
+use std::fmt::Display;
+
+use heck::{ToKebabCase, ToLowerCamelCase, ToSnakeCase, ToUpperCamelCase};
+
+use super::Expr;
+use crate::error::ActionError;
+
+/// A built-in function that can be called in a pattern, e.g. `/snake(ORIGINAL)/`. Every
+/// function returns text, and works on Unicode text, not just ASCII.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub(super) enum Function {
+    Lower,
+    Upper,
+    /// Capitalizes the first letter of each word and lowercases the rest, keeping the
+    /// text between words as it is.
+    Title,
+    Snake,
+    Kebab,
+    Camel,
+    Pascal,
+    /// Removes whitespace from both ends.
+    Trim,
+    /// Replaces every occurrence of the second argument with the third.
+    Replace,
+    /// Pads the start of the text with the fill character up to a width in characters.
+    Pad,
+    /// The characters from a start index up to an end index. Negative indices count back
+    /// from the end, as in Python.
+    Slice,
+    /// The second argument if the first is empty, or the file doesn't have the metadata
+    /// it reads.
+    Default,
+}
+
+/// The type of a function's parameter, checked when a pattern is parsed.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub(super) enum Type {
+    /// Any insert, string literal or function call.
+    Text,
+    /// An integer literal.
+    Int,
+    /// A string literal of exactly one character.
+    Char,
+}
+
+impl Display for Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Type::Text => write!(f, "text"),
+            Type::Int => write!(f, "a number"),
+            Type::Char => write!(f, "a single character in quotes"),
+        }
+    }
+}
+
+/// The value of an argument once evaluated.
+#[derive(PartialEq, Debug, Clone)]
+pub(super) enum Value {
+    Text(String),
+    Int(i64),
+}
+
+impl Value {
+    fn text(&self) -> &str {
+        match self {
+            Value::Text(text) => text,
+            Value::Int(_) => unreachable!("Argument types are checked by the parser"),
+        }
+    }
+
+    fn int(&self) -> i64 {
+        match self {
+            Value::Int(int) => *int,
+            Value::Text(_) => unreachable!("Argument types are checked by the parser"),
+        }
+    }
+}
+
+/// Every built-in function, with the types of its parameters.
+const BUILTINS: [(&str, Function, &[Type]); 12] = [
+    ("lower", Function::Lower, &[Type::Text]),
+    ("upper", Function::Upper, &[Type::Text]),
+    ("title", Function::Title, &[Type::Text]),
+    ("snake", Function::Snake, &[Type::Text]),
+    ("kebab", Function::Kebab, &[Type::Text]),
+    ("camel", Function::Camel, &[Type::Text]),
+    ("pascal", Function::Pascal, &[Type::Text]),
+    ("trim", Function::Trim, &[Type::Text]),
+    (
+        "replace",
+        Function::Replace,
+        &[Type::Text, Type::Text, Type::Text],
+    ),
+    ("pad", Function::Pad, &[Type::Text, Type::Int, Type::Char]),
+    (
+        "slice",
+        Function::Slice,
+        &[Type::Text, Type::Int, Type::Int],
+    ),
+    ("default", Function::Default, &[Type::Text, Type::Text]),
+];
+
+impl Function {
+    /// The function called `name`, if there is one.
+    pub(super) fn from_name(name: &str) -> Option<Self> {
+        BUILTINS
+            .iter()
+            .find(|(n, _, _)| *n == name)
+            .map(|(_, function, _)| *function)
+    }
+
+    /// The names of every function, for error messages.
+    pub(super) fn names() -> impl Iterator<Item = &'static str> {
+        BUILTINS.iter().map(|(name, _, _)| *name)
+    }
+
+    fn builtin(&self) -> &'static (&'static str, Function, &'static [Type]) {
+        BUILTINS
+            .iter()
+            .find(|(_, function, _)| function == self)
+            .expect("Every function is a builtin")
+    }
+
+    /// Checks that `args` are the right number and types for the function.
+    pub(super) fn check(&self, args: &[Expr]) -> Result<(), String> {
+        let (name, _, params) = self.builtin();
+        if args.len() != params.len() {
+            return Err(format!(
+                "{name} takes {} argument{}, got {}",
+                params.len(),
+                if params.len() == 1 { "" } else { "s" },
+                args.len()
+            ));
+        }
+        for (i, (arg, param)) in args.iter().zip(params.iter()).enumerate() {
+            let accepted = match (param, arg) {
+                (Type::Text, Expr::Int(_)) => false,
+                (Type::Text, _) => true,
+                (Type::Int, arg) => matches!(arg, Expr::Int(_)),
+                (Type::Char, Expr::Text(text)) => text.chars().count() == 1,
+                (Type::Char, _) => false,
+            };
+            if !accepted {
+                return Err(format!("argument {} of {name} must be {param}", i + 1));
+            }
+        }
+        Ok(())
+    }
+
+    /// Calls the function, evaluating `args` with `eval`. The arguments have been checked
+    /// by [`Function::check`].
+    pub(super) fn call(
+        &self,
+        args: &[Expr],
+        eval: impl Fn(&Expr) -> Result<Value, ActionError>,
+    ) -> Result<String, ActionError> {
+        if let Function::Default = self {
+            return match eval(&args[0]) {
+                Ok(Value::Text(text)) if !text.is_empty() => Ok(text),
+                Ok(_)
+                | Err(ActionError::MissingMetadata(_))
+                | Err(ActionError::TimestampUnavailable { .. }) => {
+                    eval(&args[1]).map(|value| value.text().to_owned())
+                }
+                Err(e) => Err(e),
+            };
+        }
+        let values = args.iter().map(eval).collect::<Result<Vec<_>, _>>()?;
+        Ok(self.apply(&values))
+    }
+
+    fn apply(&self, args: &[Value]) -> String {
+        let text = args[0].text();
+        match self {
+            Function::Lower => text.to_lowercase(),
+            Function::Upper => text.to_uppercase(),
+            Function::Title => title_case(text),
+            Function::Snake => text.to_snake_case(),
+            Function::Kebab => text.to_kebab_case(),
+            Function::Camel => text.to_lower_camel_case(),
+            Function::Pascal => text.to_upper_camel_case(),
+            Function::Trim => text.trim().to_owned(),
+            Function::Replace => text.replace(args[1].text(), args[2].text()),
+            Function::Pad => {
+                let width = usize::try_from(args[1].int()).unwrap_or_default();
+                let fill = args[2].text();
+                let missing = width.saturating_sub(text.chars().count());
+                fill.repeat(missing) + text
+            }
+            Function::Slice => {
+                let len = text.chars().count();
+                let index = |i: i64| {
+                    let i = if i < 0 {
+                        len.saturating_sub(i.unsigned_abs() as usize)
+                    } else {
+                        i as usize
+                    };
+                    i.min(len)
+                };
+                let (start, end) = (index(args[1].int()), index(args[2].int()));
+                text.chars()
+                    .skip(start)
+                    .take(end.saturating_sub(start))
+                    .collect()
+            }
+            Function::Default => unreachable!("Default is evaluated lazily in call"),
+        }
+    }
+}
+
+fn title_case(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut prev: Option<char> = None;
+    for c in text.chars() {
+        // An apostrophe doesn't start a new word, e.g. "don't".
+        let word_start = prev.is_none_or(|p| !p.is_alphanumeric() && p != '\'' && p != '’');
+        if word_start {
+            out.extend(c.to_uppercase());
+        } else {
+            out.extend(c.to_lowercase());
+        }
+        prev = Some(c);
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Function, Value};
+    use crate::{
+        error::ActionError,
+        patterns::{Expr, PatternInsert},
+    };
+
+    /// Evaluates `arg` without a file: an insert stands for an EXIF or audio tag the file
+    /// doesn't have.
+    fn eval(arg: &Expr) -> Result<Value, ActionError> {
+        match arg {
+            Expr::Text(text) => Ok(Value::Text(text.clone())),
+            Expr::Int(int) => Ok(Value::Int(*int)),
+            Expr::Insert(_) => Err(ActionError::MissingMetadata("EXIF Model".to_owned())),
+            Expr::Call(function, args) => function.call(args, eval).map(Value::Text),
+        }
+    }
+
+    fn call(name: &str, args: &[Expr]) -> Result<String, ActionError> {
+        let function = Function::from_name(name).unwrap();
+        function.check(args).unwrap();
+        function.call(args, eval)
+    }
+
+    fn text(s: &str) -> Expr {
+        Expr::Text(s.to_owned())
+    }
+
+    #[test]
+    fn case_functions() {
+        let apply = |name: &str, s: &str| call(name, &[text(s)]).unwrap();
+        assert_eq!(apply("lower", "ÉCOLE Straße"), "école straße");
+        assert_eq!(apply("upper", "école straße"), "ÉCOLE STRASSE");
+        assert_eq!(
+            apply("title", "the ÉCOLE's_report-draft"),
+            "The École's_Report-Draft"
+        );
+        assert_eq!(apply("snake", "Holiday Photos ÄÖÜ"), "holiday_photos_äöü");
+        assert_eq!(apply("kebab", "myHTTPServer"), "my-http-server");
+        assert_eq!(apply("camel", "über_cool file"), "überCoolFile");
+        assert_eq!(apply("pascal", "über_cool file"), "ÜberCoolFile");
+        assert!(Function::from_name("reverse").is_none());
+    }
+
+    #[test]
+    fn trim() {
+        assert_eq!(call("trim", &[text(" \tdraft \n")]).unwrap(), "draft");
+    }
+
+    #[test]
+    fn replace() {
+        assert_eq!(
+            call("replace", &[text("a b  c"), text(" "), text("_")]).unwrap(),
+            "a_b__c"
+        );
+    }
+
+    #[test]
+    fn pad() {
+        assert_eq!(
+            call("pad", &[text("7"), Expr::Int(3), text("0")]).unwrap(),
+            "007"
+        );
+        assert_eq!(
+            call("pad", &[text("ünï"), Expr::Int(2), text("0")]).unwrap(),
+            "ünï"
+        );
+    }
+
+    #[test]
+    fn slice() {
+        let slice = |start, end| call("slice", &[text("äbcdef"), Expr::Int(start), Expr::Int(end)]);
+        assert_eq!(slice(0, 3).unwrap(), "äbc");
+        assert_eq!(slice(-2, 100).unwrap(), "ef");
+        assert_eq!(slice(4, 2).unwrap(), "");
+    }
+
+    #[test]
+    fn default() {
+        assert_eq!(
+            call("default", &[text(""), text("unknown")]).unwrap(),
+            "unknown"
+        );
+        assert_eq!(call("default", &[text("x"), text("unknown")]).unwrap(), "x");
+        assert_eq!(
+            call(
+                "default",
+                &[Expr::Insert(PatternInsert::Original), text("unknown")]
+            )
+            .unwrap(),
+            "unknown"
+        );
+    }
+
+    #[test]
+    fn nested_calls() {
+        let trim = Expr::Call(Function::Trim, vec![text(" x ")]);
+        assert_eq!(call("upper", &[trim]).unwrap(), "X");
+        // An argument that fails falls back in an outer default.
+        let lower = Expr::Call(Function::Lower, vec![Expr::Insert(PatternInsert::Original)]);
+        assert_eq!(
+            call("default", &[lower, text("unknown")]).unwrap(),
+            "unknown"
+        );
+    }
+
+    #[test]
+    fn checks_arguments() {
+        let check = |name: &str, args: &[Expr]| Function::from_name(name).unwrap().check(args);
+        assert!(check("lower", &[text("a"), text("b")]).is_err());
+        assert!(check("lower", &[Expr::Int(1)]).is_err());
+        assert!(check("pad", &[text("a"), text("3"), text("0")]).is_err());
+        assert!(check("pad", &[text("a"), Expr::Int(3), text("00")]).is_err());
+        assert!(check("pad", &[text("a"), Expr::Int(3), text("0")]).is_ok());
+    }
+}
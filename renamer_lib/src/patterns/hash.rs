@@ -0,0 +1,197 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt::Write,
+    fs::File,
+    io::{self, Read},
+    path::Path,
+};
+
+use md5::Md5;
+use sha2::{Digest, Sha256};
+
+use crate::error::ActionError;
+
+/// A hash of a file's contents, written as lowercase hex and cut to `len` characters,
+/// e.g. `/HASH:sha256:12/`.
+#[derive(PartialEq, Debug, Clone)]
+pub(super) struct HashInsert {
+    algorithm: HashAlgorithm,
+    len: usize,
+}
+
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
+pub(super) enum HashAlgorithm {
+    Sha256,
+    Blake3,
+    Crc32,
+    Md5,
+}
+
+impl HashAlgorithm {
+    pub(super) const ALL: [HashAlgorithm; 4] = [
+        HashAlgorithm::Sha256,
+        HashAlgorithm::Blake3,
+        HashAlgorithm::Crc32,
+        HashAlgorithm::Md5,
+    ];
+
+    pub(super) fn name(&self) -> &'static str {
+        match self {
+            HashAlgorithm::Sha256 => "sha256",
+            HashAlgorithm::Blake3 => "blake3",
+            HashAlgorithm::Crc32 => "crc32",
+            HashAlgorithm::Md5 => "md5",
+        }
+    }
+
+    /// The number of hex characters in the full hash.
+    pub(super) fn hex_len(&self) -> usize {
+        match self {
+            HashAlgorithm::Sha256 | HashAlgorithm::Blake3 => 64,
+            HashAlgorithm::Crc32 => 8,
+            HashAlgorithm::Md5 => 32,
+        }
+    }
+
+    /// Hashes everything `reader` produces.
+    fn hash(&self, reader: impl Read) -> io::Result<Vec<u8>> {
+        Ok(match self {
+            HashAlgorithm::Sha256 => {
+                let mut hasher = Sha256::new();
+                stream(reader, |block| hasher.update(block))?;
+                hasher.finalize().to_vec()
+            }
+            HashAlgorithm::Blake3 => {
+                let mut hasher = blake3::Hasher::new();
+                stream(reader, |block| {
+                    hasher.update(block);
+                })?;
+                hasher.finalize().as_bytes().to_vec()
+            }
+            HashAlgorithm::Crc32 => {
+                let mut hasher = crc32fast::Hasher::new();
+                stream(reader, |block| hasher.update(block))?;
+                hasher.finalize().to_be_bytes().to_vec()
+            }
+            HashAlgorithm::Md5 => {
+                let mut hasher = Md5::new();
+                stream(reader, |block| hasher.update(block))?;
+                hasher.finalize().to_vec()
+            }
+        })
+    }
+}
+
+/// Feeds `reader` to `update` a block at a time, so large files aren't read into memory.
+fn stream(mut reader: impl Read, mut update: impl FnMut(&[u8])) -> io::Result<()> {
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        match reader.read(&mut buf) {
+            Ok(0) => return Ok(()),
+            Ok(n) => update(&buf[..n]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+impl HashInsert {
+    /// `len` has to be between 1 and the algorithm's [`HashAlgorithm::hex_len`].
+    pub(super) fn new(algorithm: HashAlgorithm, len: usize) -> Self {
+        Self { algorithm, len }
+    }
+
+    pub(super) fn read(&self, fpath: &Path, cache: &HashCache) -> Result<String, ActionError> {
+        let hex = cache.get(self.algorithm, fpath)?;
+        Ok(hex[..self.len].to_owned())
+    }
+}
+
+/// The hashes of one file, so a file is only read once per algorithm however many of the
+/// patterns renaming it use its hash.
+#[derive(Debug, Default)]
+pub(super) struct HashCache(RefCell<HashMap<HashAlgorithm, String>>);
+
+impl HashCache {
+    fn get(&self, algorithm: HashAlgorithm, fpath: &Path) -> Result<String, ActionError> {
+        if let Some(hex) = self.0.borrow().get(&algorithm) {
+            return Ok(hex.clone());
+        }
+        let hash = algorithm.hash(File::open(fpath)?)?;
+        let hex = hash.iter().fold(String::new(), |mut acc, b| {
+            let _ = write!(acc, "{b:02x}");
+            acc
+        });
+        self.0.borrow_mut().insert(algorithm, hex.clone());
+        Ok(hex)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        fs,
+        io::{self, Read},
+    };
+
+    use sha2::{Digest, Sha256};
+
+    use super::{HashAlgorithm, HashCache, HashInsert};
+
+    fn hex(algorithm: HashAlgorithm, data: &[u8]) -> String {
+        algorithm
+            .hash(data)
+            .unwrap()
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect()
+    }
+
+    #[test]
+    fn known_hashes() {
+        assert_eq!(
+            hex(HashAlgorithm::Sha256, b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            hex(HashAlgorithm::Blake3, b"abc"),
+            "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85"
+        );
+        assert_eq!(hex(HashAlgorithm::Crc32, b"abc"), "352441c2");
+        assert_eq!(
+            hex(HashAlgorithm::Md5, b"abc"),
+            "900150983cd24fb0d6963f7d28e17f72"
+        );
+        for algorithm in HashAlgorithm::ALL {
+            assert_eq!(hex(algorithm, b"").len(), algorithm.hex_len());
+        }
+    }
+
+    #[test]
+    fn streams_large_input() {
+        // Larger than the buffer and not a multiple of it.
+        let len = 1024 * 1024 + 7;
+        let streamed = HashAlgorithm::Sha256
+            .hash(io::repeat(7).take(len as u64))
+            .unwrap();
+        assert_eq!(streamed, Sha256::digest(vec![7; len]).to_vec());
+    }
+
+    #[test]
+    fn each_file_is_hashed_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("a");
+        fs::write(&file, "abc").unwrap();
+        let cache = HashCache::default();
+        let first = HashInsert::new(HashAlgorithm::Md5, 32)
+            .read(&file, &cache)
+            .unwrap();
+        fs::write(&file, "changed").unwrap();
+        let second = HashInsert::new(HashAlgorithm::Md5, 6)
+            .read(&file, &cache)
+            .unwrap();
+        assert_eq!(first, "900150983cd24fb0d6963f7d28e17f72");
+        assert_eq!(second, "900150");
+    }
+}
@@ -1,15 +1,40 @@
-use std::{collections::HashMap, fs, path::PathBuf};
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    fmt::{Display, Write},
+    fs, io,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
 
+use chrono::{DateTime, Local, Utc};
 use regex::Regex;
 
 pub use parser::PatternParseError;
 
-use crate::error::SendableErr;
+use audio::AudioTag;
+use exif::ExifInsert;
+use functions::{Function, Value};
+use hash::{HashCache, HashInsert};
+use replace::Replace;
+
+use crate::{
+    conflict::ConflictPolicy,
+    error::{ActionError, RenameError},
+};
+mod audio;
+mod exif;
+mod functions;
+mod hash;
 mod parser;
+mod replace;
 
 #[derive(Debug, Clone)]
 pub struct RenamePattern {
     capture_groups: HashMap<usize, Regex>,
+    /// A regex matched against the name whose groups are the capture groups, used instead
+    /// of `capture_groups`, e.g. `"^(?P<show>.+)\.S(\d+)"|`.
+    name_regex: Option<Regex>,
     elements: Vec<PatternElem>,
     preset_info: Option<&'static str>,
     input: Option<String>,
@@ -19,52 +44,104 @@ impl RenamePattern {
     pub fn randomize(options: ActionOptions) -> Self {
         Self {
             capture_groups: HashMap::default(),
+            name_regex: None,
             elements: vec![PatternElem::Insert(PatternInsert::Random)],
             preset_info: Some("Randomize"),
             input: None,
             options,
         }
     }
-    pub fn apply_to_file_name(&self, fpath: &PathBuf) -> Result<PathBuf, SendableErr> {
-        let fpath = fpath.canonicalize()?;
-        let fname = fpath.file_name().unwrap().to_string_lossy().to_string(); //TODO: Error handle
-        let mut capture_group_texts: HashMap<usize, String> = HashMap::new();
-        for (id, regex) in self.capture_groups.iter() {
-            let cap_text = regex.find_iter(&fname).fold(String::new(), |mut acc, s| {
-                acc.push_str(s.as_str());
-                acc
-            });
-            capture_group_texts.insert(*id, cap_text);
+    /// A sed-like search and replace on the name, e.g. `s/_/ /g`. See [`Replace::parse`].
+    pub fn replace(inp: &str, options: ActionOptions) -> Result<Self, PatternParseError> {
+        Ok(Self {
+            capture_groups: HashMap::default(),
+            name_regex: None,
+            elements: vec![PatternElem::Replace(Replace::parse(inp)?)],
+            preset_info: Some("Replace"),
+            input: Some(inp.to_owned()),
+            options,
+        })
+    }
+    /// Escapes `text` so a pattern inserts it as it is, e.g. `AC/DC` becomes `AC\/DC`.
+    pub fn escape(text: &str) -> String {
+        let mut escaped = String::with_capacity(text.len());
+        for c in text.chars() {
+            if parser::ESCAPABLE.contains(&c) {
+                escaped.push('\\');
+            }
+            escaped.push(c);
         }
+        escaped
+    }
+    pub fn apply_to_file_name(&self, fpath: &Path) -> Result<PathBuf, RenameError> {
+        apply_in_sequence([self], fpath, Position::default())
+    }
+
+    /// Renames `fname` as one step of a pipeline. `file` is the file on disk the name
+    /// belongs to, which inserts like `DATE_MODIFIED` read from.
+    fn apply_to_name(&self, file: &SourceFile<'_>, fname: &str) -> Result<String, ActionError> {
+        let capture_group_texts = self.captures(fname)?;
         let mut out_name = String::new();
         for element in self.elements.iter() {
             let to_push = match element {
-                PatternElem::Literal(lit) => lit,
-                PatternElem::Insert(pattern_insert) => match pattern_insert {
-                    PatternInsert::Random => &rand::random::<u32>().to_string(),
-                    PatternInsert::Original => &fname,
-                    PatternInsert::CaptureGroup(id) => capture_group_texts
-                        .get(id)
-                        .expect("Capture groups existence ensured by the parser"),
-                    PatternInsert::DateModified => {
-                        let date_time: chrono::DateTime<chrono::Local> =
-                            fs::metadata(&fpath).unwrap().modified().unwrap().into();
-                        &date_time.to_rfc3339()
-                    } //Error handle
-                    PatternInsert::Now => &chrono::Local::now().to_rfc3339(),
+                PatternElem::Literal(lit) => Cow::Borrowed(lit.as_str()),
+                PatternElem::Insert(insert) => insert.text(file, fname, &capture_group_texts)?,
+                PatternElem::Call(function, args) => Cow::Owned(
+                    function.call(args, |arg| arg.eval(file, fname, &capture_group_texts))?,
+                ),
+                // A preserved extension is left out of what the regex can match, and
+                // emptying the stem would leave only the extension.
+                PatternElem::Replace(replace) => match fname.rsplit_once('.') {
+                    Some((stem, ext))
+                        if self.options.preserve_file_extension
+                            && Path::new(fname).extension().is_some() =>
+                    {
+                        let stem = replace.apply(stem);
+                        if stem.is_empty() {
+                            return Err(ActionError::InvalidName(format!(".{ext}")));
+                        }
+                        Cow::Owned(format!("{stem}.{ext}"))
+                    }
+                    _ => Cow::Owned(replace.apply(fname)),
                 },
             };
-            out_name.push_str(to_push);
-        }
-        let mut new_path = fpath.clone();
-        new_path.pop();
-        new_path.push(out_name);
-        if self.options.preserve_file_extension {
-            if let Some(ext) = fpath.extension() {
-                new_path.set_extension(ext);
+            out_name.push_str(&to_push);
+        }
+        if self.options.preserve_file_extension
+            && let Some(ext) = Path::new(fname).extension()
+        {
+            let mut with_ext = PathBuf::from(out_name);
+            with_ext.set_extension(ext);
+            out_name = with_ext.to_string_lossy().to_string();
+        }
+        Ok(out_name)
+    }
+
+    /// The text of each capture group in `fname`. With a name regex a name it doesn't
+    /// match is an error rather than every group being empty.
+    fn captures(&self, fname: &str) -> Result<Captures, ActionError> {
+        let mut texts = Captures::default();
+        if let Some(regex) = &self.name_regex {
+            let caps = regex
+                .captures(fname)
+                .ok_or_else(|| ActionError::NoMatch(fname.to_owned()))?;
+            let text = |m: Option<regex::Match>| m.map_or("", |m| m.as_str()).to_owned();
+            for id in 1..caps.len() {
+                texts.numbered.insert(id, text(caps.get(id)));
+            }
+            for name in regex.capture_names().flatten() {
+                texts.named.insert(name.to_owned(), text(caps.name(name)));
             }
+            return Ok(texts);
         }
-        Ok(new_path)
+        for (id, regex) in self.capture_groups.iter() {
+            let cap_text = regex.find_iter(fname).fold(String::new(), |mut acc, s| {
+                acc.push_str(s.as_str());
+                acc
+            });
+            texts.numbered.insert(*id, cap_text);
+        }
+        Ok(texts)
     }
 
     pub fn preset_info(&self) -> Option<&'static str> {
@@ -75,14 +152,17 @@ impl RenamePattern {
         self.input.as_ref()
     }
 
-    pub fn options(&self) -> ActionOptions {
-        self.options
+    pub fn options(&self) -> &ActionOptions {
+        &self.options
     }
 }
 
 impl PartialEq for RenamePattern {
     fn eq(&self, other: &Self) -> bool {
-        if self.capture_groups.len() != other.capture_groups.len() {
+        if self.capture_groups.len() != other.capture_groups.len()
+            || self.name_regex.as_ref().map(Regex::as_str)
+                != other.name_regex.as_ref().map(Regex::as_str)
+        {
             return false;
         }
         if !(self.elements == other.elements) {
@@ -90,10 +170,10 @@ impl PartialEq for RenamePattern {
         } else {
             for id in self.capture_groups.keys() {
                 if let Some(l_regex) = self.capture_groups.get(id) {
-                    if let Some(r_regex) = other.capture_groups.get(id) {
-                        if !(l_regex.as_str() == r_regex.as_str()) {
-                            return false;
-                        }
+                    if let Some(r_regex) = other.capture_groups.get(id)
+                        && l_regex.as_str() != r_regex.as_str()
+                    {
+                        return false;
                     }
                 } else {
                     return false;
@@ -104,20 +184,325 @@ impl PartialEq for RenamePattern {
     }
 }
 
+/// Where a file falls in its group's [`crate::SortOrder`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Position {
+    /// Index among all of the group's files.
+    pub index: usize,
+    /// Index among the group's files in the same directory.
+    pub in_dir: usize,
+}
+
+/// The text of each capture group for one name.
+#[derive(Debug, Default)]
+struct Captures {
+    numbered: HashMap<usize, String>,
+    /// Only filled by a name regex.
+    named: HashMap<String, String>,
+}
+
+/// The file a pipeline is renaming, shared by each of its patterns.
+struct SourceFile<'a> {
+    path: &'a Path,
+    position: Position,
+    hashes: HashCache,
+}
+
+/// Applies `patterns` to the file name of `fpath` in order, each pattern seeing the name
+/// produced by the one before it.
+pub fn apply_in_sequence<'a>(
+    patterns: impl IntoIterator<Item = &'a RenamePattern>,
+    fpath: &Path,
+    position: Position,
+) -> Result<PathBuf, RenameError> {
+    let fail = |e: ActionError| RenameError::new(fpath.to_owned(), None, e);
+    if fpath.file_name().is_none() {
+        return Err(fail(ActionError::CannotRenameDotDot));
+    }
+    let canonical = resolve_parent(fpath).map_err(|e| fail(e.into()))?;
+    let mut fname = canonical
+        .file_name()
+        .ok_or(ActionError::CannotRenameDotDot)
+        .and_then(|name| {
+            name.to_str()
+                .ok_or_else(|| ActionError::Pattern(format!("{name:?} is not valid UTF-8")))
+        })
+        .map_err(fail)?
+        .to_owned();
+    let file = SourceFile {
+        path: &canonical,
+        position,
+        hashes: HashCache::default(),
+    };
+    for pattern in patterns {
+        fname = pattern.apply_to_name(&file, &fname).map_err(fail)?;
+    }
+    validate_name(&fname).map_err(fail)?;
+    Ok(canonical.with_file_name(fname))
+}
+
+/// `p` with its directory canonicalized, so the same file reached through a symlinked
+/// directory or a relative path always has the same path. The file name is kept as it
+/// is, renaming a symlink renames the link rather than the file it points to.
+pub(crate) fn resolve_parent(p: &Path) -> io::Result<PathBuf> {
+    let name = p
+        .file_name()
+        .ok_or_else(|| io::Error::from(io::ErrorKind::InvalidInput))?;
+    fs::symlink_metadata(p)?;
+    let parent = match p.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    Ok(parent.canonicalize()?.join(name))
+}
+
+/// Makes text read from a file's metadata safe to use in a name. Path separators,
+/// characters Windows doesn't allow in names and control characters become `_`.
+fn sanitize(text: &str) -> String {
+    text.trim_matches(|c: char| c.is_whitespace() || c == '\0')
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | '<' | '>' | ':' | '"' | '|' | '?' | '*' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect()
+}
+
+/// Rejects names a pattern can produce that no file can be given.
+fn validate_name(name: &str) -> Result<(), ActionError> {
+    if name.is_empty() || name == "." || name == ".." || name.contains('\0') || name.contains('/') {
+        return Err(ActionError::InvalidName(name.to_owned()));
+    }
+    Ok(())
+}
+
 #[derive(PartialEq, Debug, Clone)]
 enum PatternElem {
     Literal(String),
     Insert(PatternInsert),
+    /// A function call, e.g. `/replace(trim(cap1), " ", "_")/`. Its arguments have been
+    /// checked against the function's parameters by the parser.
+    Call(Function, Vec<Expr>),
+    /// A search and replace on the whole name, the only element of a
+    /// [`RenamePattern::replace`].
+    Replace(Replace),
 }
 
-#[derive(PartialEq, Debug, Clone, Copy)]
+impl PatternElem {
+    /// Every insert in the element, including those nested in function arguments.
+    fn inserts(&self) -> Vec<&PatternInsert> {
+        match self {
+            PatternElem::Literal(_) | PatternElem::Replace(_) => Vec::new(),
+            PatternElem::Insert(insert) => vec![insert],
+            PatternElem::Call(_, args) => args.iter().flat_map(Expr::inserts).collect(),
+        }
+    }
+}
+
+/// An argument of a function call.
+#[derive(PartialEq, Debug, Clone)]
+enum Expr {
+    Insert(PatternInsert),
+    /// A string literal, e.g. `"_"`.
+    Text(String),
+    /// An integer literal, e.g. `-3`.
+    Int(i64),
+    Call(Function, Vec<Expr>),
+}
+
+impl Expr {
+    fn inserts(&self) -> Vec<&PatternInsert> {
+        match self {
+            Expr::Insert(insert) => vec![insert],
+            Expr::Text(_) | Expr::Int(_) => Vec::new(),
+            Expr::Call(_, args) => args.iter().flat_map(Expr::inserts).collect(),
+        }
+    }
+
+    fn eval(
+        &self,
+        file: &SourceFile<'_>,
+        fname: &str,
+        captures: &Captures,
+    ) -> Result<Value, ActionError> {
+        Ok(match self {
+            Expr::Insert(insert) => Value::Text(insert.text(file, fname, captures)?.into_owned()),
+            Expr::Text(text) => Value::Text(text.clone()),
+            Expr::Int(int) => Value::Int(*int),
+            Expr::Call(function, args) => {
+                Value::Text(function.call(args, |arg| arg.eval(file, fname, captures))?)
+            }
+        })
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
 enum PatternInsert {
     Random,
     Original,
     CaptureGroup(usize),
-    DateModified,
-    Now,
+    /// A named group of the name regex, e.g. `/cap:show/`.
+    NamedGroup(String),
+    FileDate(Timestamp, DateFormat),
+    Now(DateFormat),
+    Exif(ExifInsert),
+    Tag(AudioTag),
+    Hash(HashInsert),
+    Counter(Counter),
 }
+
+/// One of a file's timestamps, for the `DATE_*` inserts.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Timestamp {
+    Modified,
+    /// The birth time, which not every filesystem records.
+    Created,
+    Accessed,
+    /// When the file's metadata last changed, only available on Unix.
+    Changed,
+}
+
+impl Display for Timestamp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Timestamp::Modified => write!(f, "modification time"),
+            Timestamp::Created => write!(f, "creation time"),
+            Timestamp::Accessed => write!(f, "access time"),
+            Timestamp::Changed => write!(f, "change time"),
+        }
+    }
+}
+
+impl Timestamp {
+    fn read(&self, fpath: &Path) -> Result<DateTime<Utc>, ActionError> {
+        let metadata = fs::metadata(fpath)?;
+        let time = match self {
+            Timestamp::Modified => metadata.modified(),
+            Timestamp::Created => metadata.created(),
+            Timestamp::Accessed => metadata.accessed(),
+            Timestamp::Changed => change_time(&metadata),
+        };
+        time.map(DateTime::from)
+            .map_err(|source| ActionError::TimestampUnavailable {
+                timestamp: *self,
+                source,
+            })
+    }
+}
+
+#[cfg(unix)]
+fn change_time(metadata: &fs::Metadata) -> io::Result<SystemTime> {
+    use std::os::unix::fs::MetadataExt;
+    let nanos = u32::try_from(metadata.ctime_nsec()).unwrap_or_default();
+    DateTime::from_timestamp(metadata.ctime(), nanos)
+        .map(SystemTime::from)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "change time out of range"))
+}
+
+#[cfg(not(unix))]
+fn change_time(_: &fs::Metadata) -> io::Result<SystemTime> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "change time is only available on Unix",
+    ))
+}
+
+/// How a date insert is written. Without a format it is RFC 3339, which contains `:`.
+#[derive(PartialEq, Debug, Clone, Default)]
+struct DateFormat {
+    /// A strftime format, checked by the parser.
+    format: Option<String>,
+    /// Write the time in UTC rather than the local timezone.
+    utc: bool,
+}
+
+impl DateFormat {
+    fn format(&self, time: DateTime<Utc>) -> Result<String, ActionError> {
+        let mut out = String::new();
+        let written = match (&self.format, self.utc) {
+            (Some(format), true) => write!(out, "{}", time.format(format)),
+            (Some(format), false) => write!(out, "{}", time.with_timezone(&Local).format(format)),
+            (None, true) => write!(out, "{}", time.to_rfc3339()),
+            (None, false) => write!(out, "{}", time.with_timezone(&Local).to_rfc3339()),
+        };
+        written.map_err(|_| ActionError::Pattern(format!("Could not format date {time}")))?;
+        Ok(out)
+    }
+}
+
+/// Numbers files in their group's order, `start + step * index`, zero padded to `pad`
+/// digits.
+#[derive(PartialEq, Debug, Clone, Copy)]
+struct Counter {
+    start: i64,
+    step: i64,
+    pad: usize,
+    /// Count each parent directory's files separately.
+    per_dir: bool,
+}
+
+impl Default for Counter {
+    fn default() -> Self {
+        Self {
+            start: 1,
+            step: 1,
+            pad: 0,
+            per_dir: false,
+        }
+    }
+}
+
+impl Counter {
+    fn value(&self, position: Position) -> Result<String, ActionError> {
+        let index = if self.per_dir {
+            position.in_dir
+        } else {
+            position.index
+        };
+        let value = i64::try_from(index)
+            .ok()
+            .and_then(|i| i.checked_mul(self.step))
+            .and_then(|offset| offset.checked_add(self.start))
+            .ok_or_else(|| ActionError::Pattern("Counter overflowed".to_owned()))?;
+        Ok(format!("{value:0pad$}", pad = self.pad))
+    }
+}
+impl PatternInsert {
+    /// The text the insert stands for in the new name of `file`, which is `fname` so far
+    /// and has the given capture group texts.
+    fn text<'a>(
+        &self,
+        file: &SourceFile<'_>,
+        fname: &'a str,
+        captures: &'a Captures,
+    ) -> Result<Cow<'a, str>, ActionError> {
+        let fpath = file.path;
+        Ok(match self {
+            PatternInsert::Random => rand::random::<u32>().to_string().into(),
+            PatternInsert::Original => fname.into(),
+            PatternInsert::CaptureGroup(id) => captures
+                .numbered
+                .get(id)
+                .expect("Capture groups existence ensured by the parser")
+                .into(),
+            PatternInsert::NamedGroup(name) => captures
+                .named
+                .get(name)
+                .expect("Capture groups existence ensured by the parser")
+                .into(),
+            PatternInsert::FileDate(timestamp, format) => {
+                format.format(timestamp.read(fpath)?)?.into()
+            }
+            PatternInsert::Now(format) => format.format(Utc::now())?.into(),
+            PatternInsert::Exif(exif) => exif.read(fpath)?.into(),
+            PatternInsert::Tag(tag) => tag.read(fpath)?.into(),
+            PatternInsert::Counter(counter) => counter.value(file.position)?.into(),
+            PatternInsert::Hash(hash) => hash.read(fpath, &file.hashes)?.into(),
+        })
+    }
+}
+
 impl<'a> TryFrom<&'a str> for PatternInsert {
     type Error = parser::PatternParseError;
 
@@ -125,8 +510,11 @@ impl<'a> TryFrom<&'a str> for PatternInsert {
         match value {
             "RAND" => Ok(Self::Random),
             "ORIG" | "ORIGINAL" => Ok(Self::Original),
-            "DATE_MODIFIED" => Ok(Self::DateModified),
-            "NOW" => Ok(Self::Now),
+            "DATE_MODIFIED" => Ok(Self::FileDate(Timestamp::Modified, DateFormat::default())),
+            "DATE_CREATED" => Ok(Self::FileDate(Timestamp::Created, DateFormat::default())),
+            "DATE_ACCESSED" => Ok(Self::FileDate(Timestamp::Accessed, DateFormat::default())),
+            "DATE_CHANGED" => Ok(Self::FileDate(Timestamp::Changed, DateFormat::default())),
+            "NOW" => Ok(Self::Now(DateFormat::default())),
             _ => Err(parser::PatternParseError::NonexistentInsert(
                 value.to_owned(),
             )),
@@ -134,25 +522,32 @@ impl<'a> TryFrom<&'a str> for PatternInsert {
     }
 }
 
-#[derive(Clone, Copy, Debug, Default)]
+#[derive(Clone, Debug, Default, PartialEq)]
 pub struct ActionOptions {
     pub preserve_file_extension: bool,
-    pub overwrite: bool,
+    pub on_conflict: ConflictPolicy,
 }
 
 impl ActionOptions {
-    pub fn new(preserve_file_extension: bool, overwrite: bool) -> Self {
+    pub fn new(preserve_file_extension: bool, on_conflict: ConflictPolicy) -> Self {
         Self {
             preserve_file_extension,
-            overwrite,
+            on_conflict,
         }
     }
 }
 #[cfg(test)]
 mod test {
-    use std::{fs, path::PathBuf};
+    use std::{
+        fs::{self, File, FileTimes},
+        path::PathBuf,
+        time::{Duration, SystemTime},
+    };
 
-    use crate::patterns::ActionOptions;
+    use crate::{
+        error::{ActionError, ErrorCategory},
+        patterns::ActionOptions,
+    };
 
     use super::RenamePattern;
 
@@ -161,7 +556,7 @@ mod test {
         let input_pattern = r#"1"^.{0,4}"2"\..*"|/cap1//RAND/#/cap2/"#;
         let input_files: Vec<PathBuf> = vec!["Ploggle.txt".into(), "Groggle.jpeg".into()];
         for file in input_files.iter() {
-            fs::File::create(file);
+            fs::File::create(file).unwrap();
         }
         let expected: Vec<(String, String)> = vec![
             ("Plog".into(), "#.txt".into()),
@@ -183,7 +578,7 @@ mod test {
             })
             .collect();
         for file in input_files.iter() {
-            fs::remove_file(file);
+            fs::remove_file(file).unwrap();
         }
         println!("{result:?}");
         for res in result.iter().map(|x| &x.1) {
@@ -192,4 +587,153 @@ mod test {
         let result: Vec<_> = result.into_iter().map(|x| (x.0, x.2)).collect();
         assert!(result == expected, "{result:?} != {expected:?}")
     }
+
+    #[test]
+    fn date_formats() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("a.txt");
+        fs::write(&file, "a").unwrap();
+        File::options()
+            .write(true)
+            .open(&file)
+            .unwrap()
+            .set_times(
+                FileTimes::new()
+                    .set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000))
+                    .set_accessed(SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000)),
+            )
+            .unwrap();
+        let rename = |input: &str| {
+            let pattern = RenamePattern::parse(input, ActionOptions::default()).unwrap();
+            let path = pattern.apply_to_file_name(&file).unwrap();
+            path.file_name().unwrap().to_string_lossy().into_owned()
+        };
+        assert_eq!(
+            rename("/DATE_MODIFIED:utc:%Y%m%d_%H%M%S/"),
+            "20231114_221320"
+        );
+        assert_eq!(rename("/DATE_MODIFIED:utc:date/"), "2023-11-14");
+        assert_eq!(rename("/DATE_MODIFIED:utc/"), "2023-11-14T22:13:20+00:00");
+        assert_eq!(rename("/NOW:%Y/").len(), 4);
+        assert_eq!(rename("/DATE_ACCESSED:utc:date/"), "2020-09-13");
+        #[cfg(unix)]
+        assert_eq!(rename("/DATE_CHANGED:%Y/").len(), 4);
+
+        // Not every filesystem records a birth time, that has to be an error, not a panic.
+        let pattern = RenamePattern::parse("/DATE_CREATED:%Y/", ActionOptions::default()).unwrap();
+        if let Err(e) = pattern.apply_to_file_name(&file) {
+            assert_eq!(e.category(), ErrorCategory::Unsupported);
+        }
+    }
+
+    #[test]
+    fn audio_tags() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("01.mp3");
+        fs::copy(
+            concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/tags.mp3"),
+            &file,
+        )
+        .unwrap();
+        let pattern = RenamePattern::parse(
+            "/TAG:artist/ - /TAG:album/ - /TAG:track:pad=2/ /TAG:title/",
+            ActionOptions::new(true, Default::default()),
+        )
+        .unwrap();
+        let path = pattern.apply_to_file_name(&file).unwrap();
+        assert_eq!(
+            path.file_name().unwrap(),
+            "AC_DC - Back in Black - 01 Hells Bells.mp3"
+        );
+    }
+
+    #[test]
+    fn case_functions() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("my holiday photo.JPG");
+        fs::write(&file, "").unwrap();
+        let rename = |input: &str| {
+            let pattern = RenamePattern::parse(input, ActionOptions::default()).unwrap();
+            let path = pattern.apply_to_file_name(&file).unwrap();
+            path.file_name().unwrap().to_string_lossy().into_owned()
+        };
+        assert_eq!(
+            rename(r#"1"^[^.]*"2"[^.]*$"|/pascal(cap1)/./lower(cap2)/"#),
+            "MyHolidayPhoto.jpg"
+        );
+        assert_eq!(rename("/title(ORIGINAL)/"), "My Holiday Photo.Jpg");
+    }
+
+    #[test]
+    fn nested_functions() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join(" My Holiday  Photo .txt");
+        fs::write(&file, "").unwrap();
+        let rename = |input: &str| {
+            let pattern = RenamePattern::parse(input, ActionOptions::default()).unwrap();
+            let path = pattern.apply_to_file_name(&file).unwrap();
+            path.file_name().unwrap().to_string_lossy().into_owned()
+        };
+        assert_eq!(
+            rename(r#"1"^[^.]*"|/replace(trim(cap1), " ", "_")/"#),
+            "My_Holiday__Photo"
+        );
+        assert_eq!(rename(r#"/pad(slice(ORIGINAL, 4, 6), 4, "0")/"#), "00Ho");
+        assert_eq!(rename(r#"/default(EXIF:Model, "unknown")/"#), "unknown");
+        // Functions can produce a '/', which can't be part of a name.
+        let pattern =
+            RenamePattern::parse(r#"/replace(ORIGINAL, " ", "/")/"#, ActionOptions::default())
+                .unwrap();
+        assert!(pattern.apply_to_file_name(&file).is_err());
+    }
+
+    #[test]
+    fn replace_preset() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("my.holiday_photo [1080p].txt");
+        fs::write(&file, "").unwrap();
+        let rename = |expr: &str, preserve_extension: bool| {
+            let options = ActionOptions::new(preserve_extension, Default::default());
+            let pattern = RenamePattern::replace(expr, options).unwrap();
+            assert_eq!(pattern.preset_info(), Some("Replace"));
+            let path = pattern.apply_to_file_name(&file).unwrap();
+            path.file_name().unwrap().to_string_lossy().into_owned()
+        };
+        assert_eq!(rename(r"s/\s*\[1080p\]//", true), "my.holiday_photo.txt");
+        assert_eq!(rename("s/[._]/ /g", true), "my holiday photo [1080p].txt");
+        assert_eq!(rename("s/[._]/ /g", false), "my holiday photo [1080p] txt");
+        assert!(RenamePattern::replace("s/a/b/q", ActionOptions::default()).is_err());
+    }
+
+    #[test]
+    fn replace_emptying_the_stem() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("a.txt");
+        fs::write(&file, "").unwrap();
+        let options = ActionOptions::new(true, Default::default());
+        let pattern = RenamePattern::replace("s/a//", options).unwrap();
+        let err = pattern.apply_to_file_name(&file).unwrap_err();
+        assert_eq!(err.category(), ErrorCategory::InvalidName);
+    }
+
+    #[test]
+    fn name_regex_groups() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("Some.Show.S01E02.1080p.mkv");
+        fs::write(&file, "").unwrap();
+        let pattern = RenamePattern::parse(
+            r#""^(?P<show>.+)\.S(\d+)E(\d+)"|/replace(cap:show, ".", " ")/ /cap2/x/cap3/"#,
+            ActionOptions::new(true, Default::default()),
+        )
+        .unwrap();
+        let path = pattern.apply_to_file_name(&file).unwrap();
+        assert_eq!(path.file_name().unwrap(), "Some Show 01x02.mkv");
+
+        // A name the regex doesn't match is reported rather than given empty groups.
+        let other = dir.path().join("notes.txt");
+        fs::write(&other, "").unwrap();
+        let err = pattern.apply_to_file_name(&other).unwrap_err();
+        assert!(matches!(err.cause(), ActionError::NoMatch(_)));
+        assert_eq!(err.category(), ErrorCategory::Pattern);
+    }
 }
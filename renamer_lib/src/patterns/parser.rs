@@ -1,18 +1,24 @@
 use std::{collections::HashMap, error::Error};
 
+use chrono::format::StrftimeItems;
 use nom::{
     self, Parser,
     branch::alt,
-    bytes::complete::tag,
-    character::{char, complete::satisfy, digit1},
-    combinator::{eof, opt},
+    bytes::complete::{tag, take_while1},
+    character::{
+        char,
+        complete::{alpha1, multispace0, one_of, satisfy},
+        digit1,
+    },
+    combinator::{cut, eof, opt, peek, recognize},
     error::ParseError,
-    multi::{many_till, many1},
+    multi::{many_till, many1, separated_list1},
+    sequence::{delimited, preceded, separated_pair, terminated},
 };
 use regex::Regex;
 use thiserror::Error;
 
-type PatternParseResult<'a, I, O> = Result<(I, O), nom::Err<PatternParseError>>;
+type PatternParseResult<'a, O> = Result<(&'a str, O), nom::Err<PatternParseError>>;
 
 #[derive(Debug, Error)]
 pub enum PatternParseError {
@@ -24,6 +30,18 @@ pub enum PatternParseError {
     NonexistentInsert(String),
     #[error("Unrecognized Capture Group! {0}")]
     NonexistentCapGroup(usize),
+    #[error("Unrecognized Named Capture Group! {0}")]
+    NonexistentNamedGroup(String),
+    #[error("Unrecognized Function!: {0}")]
+    NonexistentFunction(String),
+    #[error("Invalid Insert Argument!: {0}")]
+    InvalidInsertArgument(String),
+    #[error("Unrecognized Escape!: {0}")]
+    InvalidEscape(String),
+    #[error("Invalid Function Call!: {0}")]
+    InvalidFunctionCall(String),
+    #[error("Invalid Replace!: {0}")]
+    InvalidReplace(String),
     #[error("{0}")]
     Other(Box<dyn Error>),
 }
@@ -56,20 +74,44 @@ impl<'a> ParseError<&'a str> for PatternParseError {
     }
 }
 
-use super::{ActionOptions, PatternElem, PatternInsert, RenamePattern};
+use super::{
+    ActionOptions, AudioTag, Counter, DateFormat, ExifInsert, Expr, PatternElem, PatternInsert,
+    RenamePattern,
+    functions::Function,
+    hash::{HashAlgorithm, HashInsert},
+};
 
 impl<'a> TryFrom<&'a str> for RenamePatternIntermediate {
     type Error = nom::Err<PatternParseError>;
     fn try_from(value: &'a str) -> Result<Self, Self::Error> {
         let pattern = parse_pattern.parse(value).map(|x| x.1)?;
-        println!("{pattern:?}");
-        for elem in pattern.elements.iter() {
-            if let PatternElem::Insert(PatternInsert::CaptureGroup(cap_group)) = elem {
-                if !pattern.capture_groups.contains_key(cap_group) {
+        log::debug!("{pattern:?}");
+        for insert in pattern.elements.iter().flat_map(PatternElem::inserts) {
+            match (insert, &pattern.name_regex) {
+                (PatternInsert::CaptureGroup(cap_group), Some(regex))
+                    if *cap_group == 0 || *cap_group >= regex.captures_len() =>
+                {
+                    return Err(nom::Err::Failure(PatternParseError::NonexistentCapGroup(
+                        *cap_group,
+                    )));
+                }
+                (PatternInsert::CaptureGroup(cap_group), None)
+                    if !pattern.capture_groups.contains_key(cap_group) =>
+                {
                     return Err(nom::Err::Failure(PatternParseError::NonexistentCapGroup(
                         *cap_group,
                     )));
                 }
+                (PatternInsert::NamedGroup(name), regex)
+                    if !regex
+                        .as_ref()
+                        .is_some_and(|r| r.capture_names().flatten().any(|n| n == name)) =>
+                {
+                    return Err(nom::Err::Failure(PatternParseError::NonexistentNamedGroup(
+                        name.clone(),
+                    )));
+                }
+                _ => (),
             }
         }
         Ok(pattern)
@@ -81,6 +123,7 @@ impl RenamePattern {
         let intermediate = RenamePatternIntermediate::try_from(inp)?;
         Ok(Self {
             capture_groups: intermediate.capture_groups,
+            name_regex: intermediate.name_regex,
             elements: intermediate.elements,
             preset_info: None,
             input: Some(inp.to_owned()),
@@ -92,28 +135,47 @@ impl RenamePattern {
 #[derive(Debug)]
 struct RenamePatternIntermediate {
     capture_groups: HashMap<usize, Regex>,
+    name_regex: Option<Regex>,
     elements: Vec<PatternElem>,
 }
 
-fn parse_pattern(inp: &str) -> PatternParseResult<&str, RenamePatternIntermediate> {
-    let (inp, capture_groups) = opt(parse_capture_groups).parse(inp)?;
+fn parse_pattern(inp: &str) -> PatternParseResult<'_, RenamePatternIntermediate> {
+    let (inp, name_regex) = opt(parse_name_regex).parse(inp)?;
+    let (inp, capture_groups) = match name_regex {
+        Some(_) => (inp, None),
+        None => opt(parse_capture_groups).parse(inp)?,
+    };
     let capture_groups = capture_groups.unwrap_or_default();
     let (inp, elements) = parse_pattern_elems
         .parse_complete(inp)
-        .inspect_err(|e| println!("elems err {e}"))?;
-    Ok((inp, RenamePatternIntermediate {
-        capture_groups,
-        elements,
-    }))
+        .inspect_err(|e| log::debug!("elems err {e}"))?;
+    Ok((
+        inp,
+        RenamePatternIntermediate {
+            capture_groups,
+            name_regex,
+            elements,
+        },
+    ))
 }
 
-fn parse_capture_groups(inp: &str) -> PatternParseResult<&str, HashMap<usize, Regex>> {
+/// `"regex"|`, a regex matched against the name whose numbered and named groups are the
+/// capture groups, instead of a regex per capture group. Without the `|` the quoted text
+/// is part of the pattern itself, e.g. `"a"b`.
+fn parse_name_regex(inp: &str) -> PatternParseResult<'_, Regex> {
+    let (inp, _) = char('"').parse(inp)?;
+    let (inp, regex_text) = cap_group_regex_text.parse(inp)?;
+    let (inp, _) = char('|').parse(inp)?;
+    Ok((inp, compile_regex(regex_text).map_err(nom::Err::Failure)?))
+}
+
+fn parse_capture_groups(inp: &str) -> PatternParseResult<'_, HashMap<usize, Regex>> {
     many_till(parse_capture_group, char('|'))
         .parse(inp)
         .map(|(inp, (res, _))| (inp, res.into_iter().collect()))
 }
 
-fn parse_capture_group(inp: &str) -> PatternParseResult<&str, (usize, Regex)> {
+fn parse_capture_group(inp: &str) -> PatternParseResult<'_, (usize, Regex)> {
     log::trace!("Parsing cap group: {inp}");
     let (inp, parsed_id) = digit1().parse(inp)?;
     let group_id: usize = match str::parse::<usize>(parsed_id) {
@@ -129,69 +191,369 @@ fn parse_capture_group(inp: &str) -> PatternParseResult<&str, (usize, Regex)> {
     Ok((inp, (group_id, regex)))
 }
 
-fn parse_cap_group_regex(inp: &str) -> PatternParseResult<&str, Regex> {
-    let (inp, regex_text) = many_till(satisfy(|c| c != '"'), char('"')).parse(inp)?;
-    Ok((
-        inp,
-        compile_regex(regex_text.0.into_iter().collect::<String>())
-            .map_err(|e| nom::Err::Failure(e))?,
-    ))
+fn parse_cap_group_regex(inp: &str) -> PatternParseResult<'_, Regex> {
+    let (inp, regex_text) = cap_group_regex_text.parse(inp)?;
+    Ok((inp, compile_regex(regex_text).map_err(nom::Err::Failure)?))
+}
+
+/// The text of a regex up to the closing `"`. `\"` is a quote, any other escape is kept for
+/// the regex, e.g. `\.` or `\\`.
+fn cap_group_regex_text(inp: &str) -> PatternParseResult<'_, String> {
+    let regex_escape = preceded(char('\\'), satisfy(|_| true)).map(|c| match c {
+        '"' => "\"".to_owned(),
+        c => format!("\\{c}"),
+    });
+    let (inp, regex_text) = many_till(
+        alt((regex_escape, satisfy(|c| c != '"').map(String::from))),
+        char('"'),
+    )
+    .parse_complete(inp)?;
+    Ok((inp, regex_text.0.concat()))
 }
 
 fn compile_regex(inp: String) -> Result<Regex, PatternParseError> {
-    let parsed_regex = Regex::new(&inp).map_err(|x| PatternParseError::from(x))?;
+    let parsed_regex = Regex::new(&inp).map_err(PatternParseError::from)?;
     Ok(parsed_regex)
 }
 
-fn parse_pattern_elems(inp: &str) -> PatternParseResult<&str, Vec<PatternElem>> {
+fn parse_pattern_elems(inp: &str) -> PatternParseResult<'_, Vec<PatternElem>> {
     many_till(parse_pattern_elem, eof)
         .parse_complete(inp)
         .map(|(inp, (res, _))| (inp, res))
 }
 
-fn parse_pattern_elem(inp: &str) -> PatternParseResult<&str, PatternElem> {
+fn parse_pattern_elem(inp: &str) -> PatternParseResult<'_, PatternElem> {
     alt((
-        // parse_function,
+        parse_function,
         parse_capture_group_insert,
+        parse_counter_insert,
         parse_insert,
         parse_literal,
     ))
     .parse_complete(inp)
 }
 
-fn parse_literal(inp: &str) -> PatternParseResult<&str, PatternElem> {
-    many1(satisfy(|c| c != '/'))
+/// Text up to the next `/`, where a backslash before one of [`ESCAPABLE`] stands for that
+/// character, e.g. `AC\/DC`.
+fn parse_literal(inp: &str) -> PatternParseResult<'_, PatternElem> {
+    many1(alt((escape, satisfy(|c| c != '/' && c != '\\'))))
         .parse_complete(inp)
         .map(|(inp, res)| (inp, PatternElem::Literal(res.into_iter().collect())))
 }
 
-fn parse_insert(inp: &str) -> PatternParseResult<&str, PatternElem> {
-    let orig_inp = inp;
+/// The characters that have to be escaped with a backslash to appear in a literal or string.
+pub(super) const ESCAPABLE: [char; 4] = ['/', '|', '"', '\\'];
+
+/// A backslash followed by one of [`ESCAPABLE`], standing for that character.
+fn escape(inp: &str) -> PatternParseResult<'_, char> {
+    let (inp, _) = char('\\').parse_complete(inp)?;
+    match inp.chars().next() {
+        Some(c) if ESCAPABLE.contains(&c) => Ok((&inp[c.len_utf8()..], c)),
+        c => Err(nom::Err::Failure(PatternParseError::InvalidEscape(
+            format!(
+                "\\{}, expected one of \\/ \\| \\\" \\\\",
+                c.map(String::from).unwrap_or_default()
+            ),
+        ))),
+    }
+}
+
+fn parse_insert(inp: &str) -> PatternParseResult<'_, PatternElem> {
+    delimited(char('/'), named_insert(&[]), char('/'))
+        .map(PatternElem::Insert)
+        .parse(inp)
+}
+
+fn parse_capture_group_insert(inp: &str) -> PatternParseResult<'_, PatternElem> {
+    delimited(char('/'), capture_group, char('/'))
+        .map(PatternElem::Insert)
+        .parse(inp)
+}
+
+/// `/N/`, optionally followed by arguments, e.g. `/N:start=0,step=10,pad=3,reset=dir/`.
+fn parse_counter_insert(inp: &str) -> PatternParseResult<'_, PatternElem> {
+    delimited(char('/'), counter, char('/'))
+        .map(PatternElem::Insert)
+        .parse(inp)
+}
+
+/// `/function(args)/`, e.g. `/lower(cap1)/` or `/replace(trim(cap1), " ", "_")/`.
+fn parse_function(inp: &str) -> PatternParseResult<'_, PatternElem> {
     let (inp, _) = char('/').parse(inp)?;
+    let (inp, (function, args)) = call.parse(inp)?;
+    let (inp, _) = cut(char('/')).parse(inp)?;
+    Ok((inp, PatternElem::Call(function, args)))
+}
 
-    let (inp, insert_chars) = many1(satisfy(|c| c != '/')).parse(inp)?;
-    let insert_string: String = insert_chars.into_iter().collect();
-    let insert = match PatternInsert::try_from(insert_string.as_str()) {
-        Ok(i) => i,
-        Err(_) => {
-            return Err(nom::Err::Failure(PatternParseError::NonexistentInsert(
-                orig_inp.to_owned(),
+/// `name(arg, ...)`, with the arguments checked against the function's parameters.
+fn call(inp: &str) -> PatternParseResult<'_, (Function, Vec<Expr>)> {
+    let (inp, name) = alpha1.parse(inp)?;
+    let (inp, _) = char('(').parse(inp)?;
+    let function = Function::from_name(name).ok_or_else(|| {
+        nom::Err::Failure(PatternParseError::NonexistentFunction(format!(
+            "{name}, expected one of {}",
+            Function::names().collect::<Vec<_>>().join(", ")
+        )))
+    })?;
+    let (inp, args) = cut(separated_list1(
+        char(','),
+        delimited(multispace0, expr, multispace0),
+    ))
+    .parse(inp)?;
+    let (inp, _) = cut(char(')')).parse(inp)?;
+    function
+        .check(&args)
+        .map_err(|e| nom::Err::Failure(PatternParseError::InvalidFunctionCall(e)))?;
+    Ok((inp, (function, args)))
+}
+
+/// A function argument: a call, a string or integer literal, or an insert written as it
+/// would be between slashes.
+fn expr(inp: &str) -> PatternParseResult<'_, Expr> {
+    let arg_end = || peek((multispace0, one_of(",)")));
+    alt((
+        call.map(|(function, args)| Expr::Call(function, args)),
+        string_literal.map(Expr::Text),
+        terminated(int_literal, arg_end()).map(Expr::Int),
+        terminated(capture_group, arg_end()).map(Expr::Insert),
+        terminated(counter, arg_end()).map(Expr::Insert),
+        named_insert(&[',', ')']).map(Expr::Insert),
+    ))
+    .parse(inp)
+}
+
+/// `"text"`, with the same escapes as a literal, e.g. `\"` for a quote.
+fn string_literal(inp: &str) -> PatternParseResult<'_, String> {
+    let (inp, _) = char('"').parse(inp)?;
+    let (inp, (text, _)) = many_till(alt((escape, satisfy(|c| c != '"' && c != '\\'))), char('"'))
+        .parse(inp)
+        .map_err(|e| match e {
+            nom::Err::Error(_) => nom::Err::Failure(PatternParseError::InvalidFunctionCall(
+                format!("Unterminated string \"{inp}"),
+            )),
+            e => e,
+        })?;
+    Ok((inp, text.into_iter().collect()))
+}
+
+/// An integer, e.g. `10` or `-3`.
+fn int_literal(inp: &str) -> PatternParseResult<'_, i64> {
+    let (inp, int) = recognize((opt(char('-')), digit1())).parse(inp)?;
+    let int = str::parse(int).map_err(|_| {
+        nom::Err::Failure(PatternParseError::InvalidFunctionCall(format!(
+            "{int} is too large"
+        )))
+    })?;
+    Ok((inp, int))
+}
+
+/// `capN`, the text of capture group N, or `cap:name`, the text of a named group of the
+/// name regex.
+fn capture_group(inp: &str) -> PatternParseResult<'_, PatternInsert> {
+    let (inp, _) = tag("cap").parse(inp)?;
+    alt((
+        digit1().map(|group_id| PatternInsert::CaptureGroup(str::parse(group_id).unwrap())),
+        preceded(
+            char(':'),
+            take_while1(|c: char| c.is_alphanumeric() || c == '_'),
+        )
+        .map(|name: &str| PatternInsert::NamedGroup(name.to_owned())),
+    ))
+    .parse(inp)
+}
+
+/// An insert named by the text up to one of `ends`, e.g. `ORIGINAL` or `NOW:%Y`.
+fn named_insert(ends: &[char]) -> impl Fn(&str) -> PatternParseResult<'_, PatternInsert> {
+    move |inp| {
+        let orig_inp = inp;
+        let (inp, insert_chars) = many1(satisfy(|c| c != '/' && !ends.contains(&c))).parse(inp)?;
+        let insert_string = insert_chars.into_iter().collect::<String>();
+        let insert_string = insert_string.trim_end();
+        let (name, args) = match insert_string.split_once(':') {
+            Some((name, args)) => (name, Some(args)),
+            None => (insert_string, None),
+        };
+        match insert_with_args(name, args) {
+            Ok(insert) => Ok((inp, insert)),
+            Err(PatternParseError::NonexistentInsert(_)) => Err(nom::Err::Failure(
+                PatternParseError::NonexistentInsert(orig_inp.to_owned()),
+            )),
+            Err(e) => Err(nom::Err::Failure(e)),
+        }
+    }
+}
+
+/// The insert called `name`, with the text after the first `:` as `args`.
+fn insert_with_args(name: &str, args: Option<&str>) -> Result<PatternInsert, PatternParseError> {
+    match name {
+        "EXIF" => return parse_exif(args.unwrap_or_default()),
+        "TAG" => return parse_audio_tag(args.unwrap_or_default()),
+        "HASH" => return parse_hash(args.unwrap_or_default()),
+        _ => {}
+    }
+    Ok(match (PatternInsert::try_from(name)?, args) {
+        (insert, None) => insert,
+        (PatternInsert::FileDate(timestamp, _), Some(args)) => {
+            PatternInsert::FileDate(timestamp, parse_date_format(args)?)
+        }
+        (PatternInsert::Now(_), Some(args)) => PatternInsert::Now(parse_date_format(args)?),
+        (_, Some(args)) => {
+            return Err(PatternParseError::InvalidInsertArgument(format!(
+                "{name} takes no arguments, got {args:?}"
             )));
         }
+    })
+}
+
+/// `EXIF:Tag`, followed by a strftime format for date tags, e.g.
+/// `EXIF:DateTimeOriginal:%Y-%m-%d`.
+fn parse_exif(args: &str) -> Result<PatternInsert, PatternParseError> {
+    let (tag, format) = match args.split_once(':') {
+        Some((tag, format)) => (tag, Some(format)),
+        None => (args, None),
     };
-    let (inp, _) = char('/').parse(inp)?;
+    if tag.is_empty() || !tag.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err(PatternParseError::InvalidInsertArgument(format!(
+            "EXIF needs a tag name, e.g. /EXIF:Model/, got {tag:?}"
+        )));
+    }
+    if let Some(format) = format {
+        if !ExifInsert::is_date(tag) {
+            return Err(PatternParseError::InvalidInsertArgument(format!(
+                "Only EXIF date tags take a format, got {tag}:{format}"
+            )));
+        }
+        check_date_format(format)?;
+    }
+    Ok(PatternInsert::Exif(ExifInsert::new(
+        tag.to_owned(),
+        format.map(str::to_owned),
+    )))
+}
 
-    Ok((inp, PatternElem::Insert(insert)))
+/// `TAG:name`, optionally followed by `:pad=N`, e.g. `TAG:track:pad=2`.
+fn parse_audio_tag(args: &str) -> Result<PatternInsert, PatternParseError> {
+    let (name, options) = match args.split_once(':') {
+        Some((name, options)) => (name, Some(options)),
+        None => (args, None),
+    };
+    let mut pad = 0;
+    for option in options.into_iter().flat_map(|o| o.split(',')) {
+        pad = option
+            .strip_prefix("pad=")
+            .and_then(|p| p.parse().ok())
+            .ok_or_else(|| {
+                PatternParseError::InvalidInsertArgument(format!(
+                    "Unknown option {option:?} for TAG:{name}, expected pad=N"
+                ))
+            })?;
+    }
+    AudioTag::new(name, pad)
+        .map(PatternInsert::Tag)
+        .ok_or_else(|| {
+            PatternParseError::InvalidInsertArgument(format!(
+                "Unknown tag {name:?}, expected one of {}",
+                AudioTag::names().collect::<Vec<_>>().join(", ")
+            ))
+        })
 }
-fn parse_capture_group_insert(inp: &str) -> PatternParseResult<&str, PatternElem> {
-    let (inp, _) = char('/').parse(inp)?;
-    let (inp, _) = tag("cap").parse(inp)?;
-    let (inp, group_id) = digit1().parse(inp)?;
-    let (inp, _) = char('/').parse(inp)?;
-    Ok((
-        inp,
-        PatternElem::Insert(PatternInsert::CaptureGroup(str::parse(group_id).unwrap())),
-    ))
+
+/// `HASH:algorithm`, optionally followed by the number of hex characters to keep, e.g.
+/// `HASH:sha256:12`.
+fn parse_hash(args: &str) -> Result<PatternInsert, PatternParseError> {
+    let (name, len) = match args.split_once(':') {
+        Some((name, len)) => (name, Some(len)),
+        None => (args, None),
+    };
+    let algorithm = HashAlgorithm::ALL
+        .into_iter()
+        .find(|a| a.name() == name)
+        .ok_or_else(|| {
+            PatternParseError::InvalidInsertArgument(format!(
+                "Unknown hash {name:?}, expected one of {}",
+                HashAlgorithm::ALL.map(|a| a.name()).join(", ")
+            ))
+        })?;
+    let len = match len {
+        None => algorithm.hex_len(),
+        Some(len) => len
+            .parse()
+            .ok()
+            .filter(|len| (1..=algorithm.hex_len()).contains(len))
+            .ok_or_else(|| {
+                PatternParseError::InvalidInsertArgument(format!(
+                    "A {name} hash is 1 to {} characters long, got {len:?}",
+                    algorithm.hex_len()
+                ))
+            })?,
+    };
+    Ok(PatternInsert::Hash(HashInsert::new(algorithm, len)))
+}
+
+fn check_date_format(format: &str) -> Result<(), PatternParseError> {
+    StrftimeItems::new(format).parse().map(|_| ()).map_err(|_| {
+        PatternParseError::InvalidInsertArgument(format!("Bad date format {format:?}"))
+    })
+}
+
+/// The arguments of a date insert: an optional `utc` or `local` timezone, then a strftime
+/// format, e.g. `utc:%Y%m%d`. `date` is short for `%Y-%m-%d`, and no format means RFC 3339.
+fn parse_date_format(args: &str) -> Result<DateFormat, PatternParseError> {
+    let (utc, format) = match args.split_once(':').unwrap_or((args, "")) {
+        ("utc", format) => (true, format),
+        ("local", format) => (false, format),
+        _ => (false, args),
+    };
+    let format = match format {
+        "" => None,
+        "date" => Some("%Y-%m-%d".to_owned()),
+        format => {
+            check_date_format(format)?;
+            Some(format.to_owned())
+        }
+    };
+    Ok(DateFormat { format, utc })
+}
+
+/// `N`, optionally followed by arguments, e.g. `N:start=0,step=10,pad=3,reset=dir`.
+fn counter(inp: &str) -> PatternParseResult<'_, PatternInsert> {
+    let (inp, _) = tag("N").parse(inp)?;
+    let (inp, args) = opt(preceded(char(':'), parse_insert_args)).parse(inp)?;
+    let mut counter = Counter::default();
+    for (key, value) in args.unwrap_or_default() {
+        let invalid = || {
+            nom::Err::Failure(PatternParseError::InvalidInsertArgument(format!(
+                "{key}={value}"
+            )))
+        };
+        match key {
+            "start" => counter.start = value.parse().map_err(|_| invalid())?,
+            "step" => counter.step = value.parse().map_err(|_| invalid())?,
+            "pad" => counter.pad = value.parse().map_err(|_| invalid())?,
+            "reset" => {
+                counter.per_dir = match value {
+                    "dir" => true,
+                    "none" => false,
+                    _ => return Err(invalid()),
+                }
+            }
+            _ => return Err(invalid()),
+        }
+    }
+    Ok((inp, PatternInsert::Counter(counter)))
+}
+
+/// Comma separated `key=value` pairs, ending at the insert's closing `/` or `)`.
+fn parse_insert_args(inp: &str) -> PatternParseResult<'_, Vec<(&str, &str)>> {
+    separated_list1(
+        char(','),
+        separated_pair(
+            take_while1(|c: char| c.is_ascii_alphanumeric() || c == '_'),
+            char('='),
+            take_while1(|c| c != ',' && c != '/' && c != ')'),
+        ),
+    )
+    .parse(inp)
 }
 
 #[cfg(test)]
@@ -200,7 +562,12 @@ mod test {
 
     use regex::Regex;
 
-    use crate::patterns::{ActionOptions, PatternElem, PatternInsert, RenamePattern};
+    use crate::patterns::{
+        ActionOptions, AudioTag, Counter, DateFormat, ExifInsert, Expr, PatternElem, PatternInsert,
+        RenamePattern, Timestamp,
+        functions::Function,
+        hash::{HashAlgorithm, HashInsert},
+    };
 
     #[test]
     fn basic_test() {
@@ -212,6 +579,7 @@ mod test {
             ]
             .into_iter()
             .collect(),
+            name_regex: None,
             elements: vec![
                 PatternElem::Insert(PatternInsert::CaptureGroup(1)),
                 PatternElem::Insert(PatternInsert::Random),
@@ -227,11 +595,67 @@ mod test {
             panic!("res: {res:?} != expected: {expected:?}")
         }
     }
+    #[test]
+    fn name_regex() {
+        let input = r#""^(?P<show>.+)\.S(\d+)E(\d+)"|/cap:show/ /cap2/x/pad(cap3, 2, "0")/"#;
+        let expected = RenamePattern {
+            capture_groups: HashMap::default(),
+            name_regex: Some(Regex::new(r"^(?P<show>.+)\.S(\d+)E(\d+)").unwrap()),
+            elements: vec![
+                PatternElem::Insert(PatternInsert::NamedGroup("show".to_owned())),
+                PatternElem::Literal(" ".to_owned()),
+                PatternElem::Insert(PatternInsert::CaptureGroup(2)),
+                PatternElem::Literal("x".to_owned()),
+                PatternElem::Call(
+                    Function::Pad,
+                    vec![
+                        Expr::Insert(PatternInsert::CaptureGroup(3)),
+                        Expr::Int(2),
+                        Expr::Text("0".to_owned()),
+                    ],
+                ),
+            ],
+            preset_info: None,
+            input: Some(input.to_owned()),
+            options: ActionOptions::default(),
+        };
+        let parse = |input: &str| RenamePattern::parse(input, ActionOptions::default());
+        assert_eq!(parse(input).unwrap(), expected);
+        // Groups the regex doesn't have.
+        assert!(parse(r#""(a)(?P<b>b)"|/cap0/"#).is_err());
+        assert!(parse(r#""(a)(?P<b>b)"|/cap3/"#).is_err());
+        assert!(parse(r#""(a)(?P<b>b)"|/cap:c/"#).is_err());
+        assert!(parse(r#""(a)(?P<b>b)"|/cap2//cap:b/"#).is_ok());
+        // Named groups only come from a name regex.
+        assert!(parse(r#"1"a"|/cap:a/"#).is_err());
+        assert!(parse(r#""(a)"/cap1/"#).is_err());
+    }
+
+    #[test]
+    fn leading_quoted_literal() {
+        // Without a `|` after it, quoted text at the start is a literal, as it was before
+        // name regexes.
+        let elements =
+            |input: &str| RenamePattern::parse(input, ActionOptions::default()).map(|p| p.elements);
+        assert_eq!(
+            elements(r#""a"b"#).unwrap(),
+            [PatternElem::Literal(r#""a"b"#.to_owned())]
+        );
+        assert_eq!(
+            elements(r#""(a"/ORIGINAL/"#).unwrap(),
+            [
+                PatternElem::Literal(r#""(a""#.to_owned()),
+                PatternElem::Insert(PatternInsert::Original),
+            ]
+        );
+    }
+
     #[test]
     fn no_capture_groups() {
         let input = "/RAND/hello/RAND/";
         let expected = RenamePattern {
             capture_groups: HashMap::default(),
+            name_regex: None,
             elements: vec![
                 PatternElem::Insert(PatternInsert::Random),
                 PatternElem::Literal("hello".to_owned()),
@@ -249,6 +673,7 @@ mod test {
         let input = "hello";
         let expected = RenamePattern {
             capture_groups: HashMap::default(),
+            name_regex: None,
             elements: vec![PatternElem::Literal("hello".to_owned())],
             preset_info: None,
             input: Some(input.to_owned()),
@@ -257,4 +682,289 @@ mod test {
         let res = RenamePattern::parse(input, ActionOptions::default()).unwrap();
         assert!(res == expected)
     }
+    #[test]
+    fn counter_args() {
+        let elements =
+            |input: &str| RenamePattern::parse(input, ActionOptions::default()).map(|p| p.elements);
+        assert_eq!(
+            elements("/N/").unwrap(),
+            [PatternElem::Insert(PatternInsert::Counter(
+                Counter::default()
+            ))]
+        );
+        assert_eq!(
+            elements("img_/N:start=0,step=-2,pad=3,reset=dir/").unwrap(),
+            [
+                PatternElem::Literal("img_".to_owned()),
+                PatternElem::Insert(PatternInsert::Counter(Counter {
+                    start: 0,
+                    step: -2,
+                    pad: 3,
+                    per_dir: true,
+                })),
+            ]
+        );
+        assert!(elements("/NOW/").is_ok());
+        assert!(elements("/N:pad=x/").is_err());
+        assert!(elements("/N:size=3/").is_err());
+        assert!(elements("/N:/").is_err());
+    }
+
+    #[test]
+    fn date_format_args() {
+        let insert = |input: &str| {
+            RenamePattern::parse(input, ActionOptions::default()).map(|mut p| p.elements.remove(0))
+        };
+        let date = |format: Option<&str>, utc| DateFormat {
+            format: format.map(str::to_owned),
+            utc,
+        };
+        assert_eq!(
+            insert("/NOW/").unwrap(),
+            PatternElem::Insert(PatternInsert::Now(date(None, false)))
+        );
+        assert_eq!(
+            insert("/DATE_MODIFIED:%Y-%m-%d/").unwrap(),
+            PatternElem::Insert(PatternInsert::FileDate(
+                Timestamp::Modified,
+                date(Some("%Y-%m-%d"), false)
+            ))
+        );
+        assert_eq!(
+            insert("/NOW:utc:%H:%M/").unwrap(),
+            PatternElem::Insert(PatternInsert::Now(date(Some("%H:%M"), true)))
+        );
+        assert_eq!(
+            insert("/NOW:local:date/").unwrap(),
+            PatternElem::Insert(PatternInsert::Now(date(Some("%Y-%m-%d"), false)))
+        );
+        assert_eq!(
+            insert("/NOW:utc/").unwrap(),
+            PatternElem::Insert(PatternInsert::Now(date(None, true)))
+        );
+        assert!(insert("/NOW:%Q/").is_err());
+        assert!(insert("/NOW:%Y%/").is_err());
+        assert!(insert("/RAND:%Y/").is_err());
+    }
+
+    #[test]
+    fn exif_args() {
+        let insert = |input: &str| {
+            RenamePattern::parse(input, ActionOptions::default()).map(|mut p| p.elements.remove(0))
+        };
+        assert_eq!(
+            insert("/EXIF:DateTimeOriginal:%Y-%m-%d/").unwrap(),
+            PatternElem::Insert(PatternInsert::Exif(ExifInsert::new(
+                "DateTimeOriginal".to_owned(),
+                Some("%Y-%m-%d".to_owned())
+            )))
+        );
+        assert_eq!(
+            insert("/EXIF:LensModel/").unwrap(),
+            PatternElem::Insert(PatternInsert::Exif(ExifInsert::new(
+                "LensModel".to_owned(),
+                None
+            )))
+        );
+        assert!(insert("/EXIF/").is_err());
+        assert!(insert("/EXIF:Model:%Y/").is_err());
+        assert!(insert("/EXIF:DateTime:%Q/").is_err());
+    }
+
+    #[test]
+    fn audio_tag_args() {
+        let insert = |input: &str| {
+            RenamePattern::parse(input, ActionOptions::default()).map(|mut p| p.elements.remove(0))
+        };
+        assert_eq!(
+            insert("/TAG:track:pad=2/").unwrap(),
+            PatternElem::Insert(PatternInsert::Tag(AudioTag::new("track", 2).unwrap()))
+        );
+        assert!(insert("/TAG:artist/").is_ok());
+        assert!(insert("/TAG/").is_err());
+        assert!(insert("/TAG:artsit/").is_err());
+        assert!(insert("/TAG:track:pad=x/").is_err());
+    }
+
+    #[test]
+    fn hash_args() {
+        let insert = |input: &str| {
+            RenamePattern::parse(input, ActionOptions::default()).map(|mut p| p.elements.remove(0))
+        };
+        assert_eq!(
+            insert("/HASH:sha256:12/").unwrap(),
+            PatternElem::Insert(PatternInsert::Hash(HashInsert::new(
+                HashAlgorithm::Sha256,
+                12
+            )))
+        );
+        assert_eq!(
+            insert("/HASH:crc32/").unwrap(),
+            PatternElem::Insert(PatternInsert::Hash(HashInsert::new(
+                HashAlgorithm::Crc32,
+                8
+            )))
+        );
+        assert!(insert("/HASH/").is_err());
+        assert!(insert("/HASH:sha1/").is_err());
+        assert!(insert("/HASH:md5:0/").is_err());
+        assert!(insert("/HASH:crc32:9/").is_err());
+    }
+
+    #[test]
+    fn function_calls() {
+        let elements =
+            |input: &str| RenamePattern::parse(input, ActionOptions::default()).map(|p| p.elements);
+        let text = |s: &str| Expr::Text(s.to_owned());
+        assert_eq!(
+            elements(r#"1"[a-z]+"|/lower(cap1)/_/snake(ORIGINAL)/"#).unwrap(),
+            [
+                PatternElem::Call(
+                    Function::Lower,
+                    vec![Expr::Insert(PatternInsert::CaptureGroup(1))]
+                ),
+                PatternElem::Literal("_".to_owned()),
+                PatternElem::Call(Function::Snake, vec![Expr::Insert(PatternInsert::Original)]),
+            ]
+        );
+        assert_eq!(
+            elements("/upper(N:pad=2)/").unwrap(),
+            [PatternElem::Call(
+                Function::Upper,
+                vec![Expr::Insert(PatternInsert::Counter(Counter {
+                    pad: 2,
+                    ..Counter::default()
+                }))]
+            )]
+        );
+        assert_eq!(
+            elements(r#"1"[a-z]+"|/replace(trim(cap1), " ", "_")/"#).unwrap(),
+            [PatternElem::Call(
+                Function::Replace,
+                vec![
+                    Expr::Call(
+                        Function::Trim,
+                        vec![Expr::Insert(PatternInsert::CaptureGroup(1))]
+                    ),
+                    text(" "),
+                    text("_"),
+                ]
+            )]
+        );
+        assert_eq!(
+            elements("/slice(ORIGINAL , 0, -3)/").unwrap(),
+            [PatternElem::Call(
+                Function::Slice,
+                vec![
+                    Expr::Insert(PatternInsert::Original),
+                    Expr::Int(0),
+                    Expr::Int(-3)
+                ]
+            )]
+        );
+        assert_eq!(
+            elements(r#"/default(EXIF:Model, "say \"hi\" \\")/"#).unwrap(),
+            [PatternElem::Call(
+                Function::Default,
+                vec![
+                    Expr::Insert(PatternInsert::Exif(ExifInsert::new(
+                        "Model".to_owned(),
+                        None
+                    ))),
+                    text(r#"say "hi" \"#),
+                ]
+            )]
+        );
+        assert!(elements(r#"/pad(N, 4, "0")/"#).is_ok());
+        assert!(elements("/title(NOW:%Y)/").is_ok());
+        assert!(elements("/reverse(ORIGINAL)/").is_err());
+        assert!(elements("/lower(cap1)/").is_err());
+        assert!(elements(r#"1"a"|/default(cap1, lower(cap2))/"#).is_err());
+        assert!(elements("/lower(FOO)/").is_err());
+        assert!(elements("/lower(ORIGINAL/").is_err());
+        // Arity and types are checked when parsing.
+        assert!(elements("/lower(ORIGINAL, ORIGINAL)/").is_err());
+        assert!(elements("/pad(ORIGINAL, \"4\", \"0\")/").is_err());
+        assert!(elements(r#"/pad(ORIGINAL, 4, "00")/"#).is_err());
+        assert!(elements("/lower(3)/").is_err());
+        // Unterminated strings and unknown escapes.
+        assert!(elements(r#"/default(ORIGINAL, "x)/"#).is_err());
+        assert!(elements(r#"/default(ORIGINAL, "\n")/"#).is_err());
+    }
+
+    fn literal(input: &str) -> Vec<PatternElem> {
+        RenamePattern::parse(input, ActionOptions::default())
+            .unwrap()
+            .elements
+    }
+
+    #[test]
+    fn escaped_slash() {
+        assert_eq!(
+            literal(r"AC\/DC/ORIGINAL/"),
+            [
+                PatternElem::Literal("AC/DC".to_owned()),
+                PatternElem::Insert(PatternInsert::Original),
+            ]
+        );
+        assert_eq!(
+            literal(r#"/default(ORIGINAL, "a\/b")/"#),
+            [PatternElem::Call(
+                Function::Default,
+                vec![
+                    Expr::Insert(PatternInsert::Original),
+                    Expr::Text("a/b".to_owned())
+                ]
+            )]
+        );
+    }
+
+    #[test]
+    fn escaped_pipe() {
+        // Without the escape, a leading `|` ends an empty list of capture groups.
+        assert_eq!(literal(r"\|a|b"), [PatternElem::Literal("|a|b".to_owned())]);
+        assert_eq!(literal("|a"), [PatternElem::Literal("a".to_owned())]);
+    }
+
+    #[test]
+    fn escaped_quote() {
+        // Without the escape, a leading quoted `"...|` starts a name regex.
+        assert_eq!(
+            literal(r#"\"quoted\""#),
+            [PatternElem::Literal(r#""quoted""#.to_owned())]
+        );
+        let pattern =
+            RenamePattern::parse(r#"1"say \"(\w+)\""|/cap1/"#, ActionOptions::default()).unwrap();
+        assert_eq!(pattern.capture_groups[&1].as_str(), r#"say "(\w+)""#);
+        let pattern =
+            RenamePattern::parse(r#""^\"(?P<q>[^\"]*)\""|/cap:q/"#, ActionOptions::default())
+                .unwrap();
+        assert_eq!(pattern.name_regex.unwrap().as_str(), r#"^"(?P<q>[^"]*)""#);
+    }
+
+    #[test]
+    fn escaped_backslash() {
+        assert_eq!(literal(r"a\\b"), [PatternElem::Literal(r"a\b".to_owned())]);
+        // Other escapes are the regex's own, including an escaped backslash before the
+        // closing quote.
+        let pattern =
+            RenamePattern::parse(r#"1"\.\d\\"|/cap1/"#, ActionOptions::default()).unwrap();
+        assert_eq!(pattern.capture_groups[&1].as_str(), r"\.\d\\");
+        // Unknown and trailing escapes in literals are errors.
+        assert!(RenamePattern::parse(r"a\nb", ActionOptions::default()).is_err());
+        assert!(RenamePattern::parse(r"a\", ActionOptions::default()).is_err());
+    }
+
+    #[test]
+    fn escapes_round_trip() {
+        let text = r#"|AC/DC "live" \ 1"#;
+        let input = RenamePattern::escape(text);
+        assert_eq!(input, r#"\|AC\/DC \"live\" \\ 1"#);
+        let pattern = RenamePattern::parse(&input, ActionOptions::default()).unwrap();
+        assert_eq!(pattern.input(), Some(&input));
+        assert_eq!(pattern.elements, [PatternElem::Literal(text.to_owned())]);
+        let reparsed =
+            RenamePattern::parse(pattern.input().unwrap(), ActionOptions::default()).unwrap();
+        assert_eq!(reparsed, pattern);
+    }
 }
@@ -0,0 +1,127 @@
+use regex::Regex;
+
+use super::parser::PatternParseError;
+
+/// A sed-like search and replace, e.g. `s/_/ /g` or `s/\[1080p\]//`. The replacement can
+/// refer to the regex's groups as `$1` or `${name}`. As in [`Regex::replace`], `$1_` is the
+/// group named `1_`, `${1}_` is group 1 followed by `_`.
+#[derive(Debug, Clone)]
+pub(super) struct Replace {
+    regex: Regex,
+    replacement: String,
+    /// Replace every match rather than only the first, the `g` flag.
+    global: bool,
+}
+
+impl Replace {
+    /// Parses `s/regex/replacement/flags`, where `\/` is a `/` in the regex or replacement
+    /// and the flags are `g` and `i`.
+    pub(super) fn parse(inp: &str) -> Result<Self, PatternParseError> {
+        let invalid = |msg: &str| PatternParseError::InvalidReplace(format!("{msg} in {inp:?}"));
+        let rest = inp
+            .strip_prefix("s/")
+            .ok_or_else(|| invalid("Expected s/regex/replacement/flags"))?;
+        let (regex, rest) = split_section(rest).ok_or_else(|| invalid("Unterminated regex"))?;
+        let (replacement, flags) =
+            split_section(rest).ok_or_else(|| invalid("Unterminated replacement"))?;
+        let (mut global, mut case_insensitive) = (false, false);
+        for flag in flags.chars() {
+            match flag {
+                'g' => global = true,
+                'i' => case_insensitive = true,
+                _ => return Err(invalid(&format!("Unknown flag {flag:?}, expected g or i"))),
+            }
+        }
+        let regex = if case_insensitive {
+            Regex::new(&format!("(?i){regex}"))?
+        } else {
+            Regex::new(&regex)?
+        };
+        Ok(Self {
+            regex,
+            replacement,
+            global,
+        })
+    }
+
+    pub(super) fn apply(&self, name: &str) -> String {
+        let limit = if self.global { 0 } else { 1 };
+        self.regex
+            .replacen(name, limit, self.replacement.as_str())
+            .into_owned()
+    }
+}
+
+impl PartialEq for Replace {
+    fn eq(&self, other: &Self) -> bool {
+        self.regex.as_str() == other.regex.as_str()
+            && self.replacement == other.replacement
+            && self.global == other.global
+    }
+}
+
+/// The text up to the next unescaped `/` with `\/` unescaped, and the text after it. Other
+/// escapes are kept, so they mean the same to the regex as they would anywhere else.
+fn split_section(inp: &str) -> Option<(String, &str)> {
+    let mut section = String::new();
+    let mut chars = inp.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '/' => return Some((section, &inp[i + 1..])),
+            '\\' => match chars.next() {
+                Some((_, '/')) => section.push('/'),
+                Some((_, escaped)) => {
+                    section.push('\\');
+                    section.push(escaped);
+                }
+                None => section.push('\\'),
+            },
+            c => section.push(c),
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::Replace;
+
+    fn replace(expr: &str, name: &str) -> String {
+        Replace::parse(expr).unwrap().apply(name)
+    }
+
+    #[test]
+    fn replaces() {
+        assert_eq!(replace("s/_/ /", "a_b_c"), "a b_c");
+        assert_eq!(replace("s/_/ /g", "a_b_c"), "a b c");
+        assert_eq!(replace(r"s/\s*\[1080p\]//", "Film [1080p]"), "Film");
+        assert_eq!(replace("s/JPEG/jpg/i", "photo.jpeg"), "photo.jpg");
+        assert_eq!(replace(r"s/a\/b/c/", "a/b"), "c");
+    }
+
+    #[test]
+    fn backreferences() {
+        assert_eq!(
+            replace(r"s/(\w+)-(\w+)/$2-$1/", "world-hello"),
+            "hello-world"
+        );
+        assert_eq!(
+            replace(
+                r"s/(?P<year>\d{4})(?P<rest>.*)/${rest}_${year}/",
+                "2024 notes"
+            ),
+            " notes_2024"
+        );
+        assert_eq!(replace(r"s/(\w+)-/$1_/", "a-b"), "b");
+        assert_eq!(replace(r"s/(\w+)-/${1}_/", "a-b"), "a_b");
+    }
+
+    #[test]
+    fn invalid_expressions() {
+        assert!(Replace::parse("/a/b/").is_err());
+        assert!(Replace::parse("s/a").is_err());
+        assert!(Replace::parse("s/a/b").is_err());
+        assert!(Replace::parse("s/a/b/x").is_err());
+        assert!(Replace::parse("s/(/b/").is_err());
+    }
+}
@@ -0,0 +1,933 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Display,
+    fs, io,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use serde::Serialize;
+
+use crate::{
+    Action, conflict,
+    error::{ActionError, RenameError},
+    moves, noclobber,
+    progress::{CancellationToken, Execution, Progress, ProgressObserver},
+    report::{Report, SkipReason},
+};
+
+/// Every rename an [`crate::ActionGroup`] would perform, computed up front so it can be
+/// inspected and filtered before anything on disk is changed.
+#[derive(Debug, Default, Serialize)]
+pub struct RenamePlan {
+    actions: Vec<Action>,
+    /// Each paired with how long it took to find out the name could not be computed.
+    #[serde(serialize_with = "serialize_failures")]
+    failures: Vec<(RenameError, Duration)>,
+}
+
+fn serialize_failures<S: serde::Serializer>(
+    failures: &[(RenameError, Duration)],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(failures.iter().map(|(e, _)| e))
+}
+
+impl RenamePlan {
+    pub(crate) fn new(generated: Vec<Result<Action, (RenameError, Duration)>>) -> Self {
+        let mut plan = Self::default();
+        for res in generated {
+            match res {
+                Ok(action) => plan.actions.push(action),
+                Err(e) => plan.failures.push(e),
+            }
+        }
+        plan
+    }
+
+    pub fn actions(&self) -> &[Action] {
+        &self.actions
+    }
+
+    /// Errors hit while computing new names, these files have no action in the plan.
+    pub fn failures(&self) -> impl Iterator<Item = &RenameError> {
+        self.failures.iter().map(|(e, _)| e)
+    }
+
+    pub fn take_failures(&mut self) -> Vec<RenameError> {
+        std::mem::take(&mut self.failures)
+            .into_iter()
+            .map(|(e, _)| e)
+            .collect()
+    }
+
+    /// What executing the plan would do to each file, without doing it. Colliding
+    /// files are reported as failures.
+    pub fn preview(self) -> Vec<Report> {
+        let start = Instant::now();
+        let collisions = self.collisions();
+        let mut colliding: HashMap<&Path, &Collision> = HashMap::new();
+        for collision in collisions.iter() {
+            for source in collision.sources() {
+                colliding.insert(source, collision);
+            }
+        }
+        let checked_in = start.elapsed();
+        self.actions
+            .iter()
+            .map(|action| match colliding.get(action.source()) {
+                Some(collision) => action.failed(
+                    ActionError::Collision((*collision).clone()),
+                    action.planned_in + checked_in,
+                ),
+                None => action.report(),
+            })
+            .chain(self.failures.into_iter().map(failed))
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.actions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.actions.is_empty()
+    }
+
+    /// Keeps only the actions for which `f` returns true.
+    pub fn retain(&mut self, f: impl FnMut(&Action) -> bool) {
+        self.actions.retain(f);
+    }
+
+    /// Groups of actions in the plan that would be renamed to the same target.
+    pub fn collisions(&self) -> Vec<Collision> {
+        let mut by_target: BTreeMap<&Path, Vec<&Path>> = BTreeMap::new();
+        for action in self.actions.iter() {
+            by_target
+                .entry(action.target())
+                .or_default()
+                .push(action.source());
+        }
+        by_target
+            .into_iter()
+            .filter(|(_, sources)| sources.len() > 1)
+            .map(|(target, sources)| Collision {
+                target: target.to_owned(),
+                sources: sources.into_iter().map(Path::to_owned).collect(),
+            })
+            .collect()
+    }
+
+    /// Runs every action in the plan. If any actions collide nothing is renamed and a
+    /// failure is reported for each colliding file instead.
+    pub fn execute(self) -> Vec<Report> {
+        self.execute_with(&|_: Progress<'_>| (), &CancellationToken::new())
+    }
+
+    /// Like [`RenamePlan::execute`], reporting each file to `observer` as it is dealt
+    /// with. Once `cancel` is cancelled no more renames are started and the files left
+    /// are reported as skipped. A rename cycle that has been started is always finished.
+    pub fn execute_with(
+        mut self,
+        observer: &impl ProgressObserver,
+        cancel: &CancellationToken,
+    ) -> Vec<Report> {
+        if let Some(reports) = self.collision_failures() {
+            return reports;
+        }
+        let exec = Execution::new(observer, cancel, self.actions.len());
+        let mut results: Vec<IndexedReport> = schedule(&self.actions)
+            .into_par_iter()
+            .flat_map(|seq| seq.execute(&self.actions, &exec))
+            .collect();
+        results.sort_by_key(|(idx, _)| *idx);
+        results
+            .into_iter()
+            .map(|(_, rep)| rep)
+            .chain(self.failures.into_iter().map(failed))
+            .collect()
+    }
+
+    /// Runs the plan all-or-nothing. The actions are executed one at a time and if any
+    /// of them fails every rename already done is reverted, and a single failure for
+    /// the file that failed with an [`ActionError::RolledBack`] cause is returned. Files
+    /// replaced under [`crate::ConflictPolicy::Overwrite`] are kept until the batch has
+    /// succeeded, so a rollback restores them too.
+    /// Nothing is renamed if the plan has collisions or failures.
+    pub fn execute_atomic(self) -> Vec<Report> {
+        self.execute_atomic_with(&|_: Progress<'_>| (), &CancellationToken::new())
+    }
+
+    /// Like [`RenamePlan::execute_atomic`], reporting progress to `observer`. Cancelling
+    /// before the plan has finished rolls back every rename done so far, as if the next
+    /// action had failed with [`ActionError::Cancelled`].
+    pub fn execute_atomic_with(
+        mut self,
+        observer: &impl ProgressObserver,
+        cancel: &CancellationToken,
+    ) -> Vec<Report> {
+        if let Some(reports) = self.collision_failures() {
+            return reports;
+        }
+        if !self.failures.is_empty() {
+            return self.failures.into_iter().map(failed).collect();
+        }
+        let start = Instant::now();
+        let exec = Execution::new(observer, cancel, self.actions.len());
+        let mut done: Vec<(usize, Report)> = Vec::new();
+        for seq in schedule(&self.actions) {
+            let (results, parked) = if exec.is_cancelled() {
+                let idx = seq.order[0];
+                (vec![(idx, self.actions[idx].cancelled())], None)
+            } else {
+                seq.run(&self.actions, &exec, true)
+            };
+            let mut failure = None;
+            for (idx, rep) in results {
+                match rep {
+                    Report::Failed { error, .. } if failure.is_none() => {
+                        failure = Some((idx, error.into_cause()))
+                    }
+                    Report::Failed { .. } => (),
+                    Report::Skipped {
+                        reason: SkipReason::Cancelled,
+                        ..
+                    } if failure.is_none() => failure = Some((idx, ActionError::Cancelled)),
+                    rep => done.push((idx, rep)),
+                }
+            }
+            if let Some((idx, cause)) = failure {
+                let failed = &self.actions[idx];
+                let rollback =
+                    Rollback::revert(failed.clone(), cause, done.into_iter().map(|(_, rep)| rep));
+                if let Some(tmp) = parked {
+                    seq.restore(&self.actions, &tmp);
+                }
+                return vec![
+                    failed.failed(ActionError::RolledBack(Box::new(rollback)), start.elapsed()),
+                ];
+            }
+        }
+        done.sort_by_key(|(idx, _)| *idx);
+        done.into_iter()
+            .map(|(_, rep)| match rep {
+                Report::Overwrote {
+                    from,
+                    to,
+                    replaced: Some(aside),
+                    copied,
+                    duration,
+                } => {
+                    remove_replaced(&aside);
+                    Report::Overwrote {
+                        from,
+                        to,
+                        replaced: None,
+                        copied,
+                        duration,
+                    }
+                }
+                rep => rep,
+            })
+            .collect()
+    }
+
+    fn collision_failures(&mut self) -> Option<Vec<Report>> {
+        let start = Instant::now();
+        let collisions = self.collisions();
+        if collisions.is_empty() {
+            return None;
+        }
+        let duration = start.elapsed();
+        Some(
+            collisions
+                .iter()
+                .flat_map(Collision::errors)
+                .map(|error| Report::Failed { error, duration })
+                .chain(std::mem::take(&mut self.failures).into_iter().map(failed))
+                .collect(),
+        )
+    }
+}
+
+fn failed((error, duration): (RenameError, Duration)) -> Report {
+    Report::Failed { error, duration }
+}
+
+/// The report for an action, tagged with its position in the plan.
+type IndexedReport = (usize, Report);
+
+/// A run of actions that depend on each other and must be executed in order, e.g. a→b
+/// can only happen once b→c has moved b out of the way.
+#[derive(Debug, PartialEq)]
+struct Sequence {
+    order: Vec<usize>,
+    /// The last action in `order` closes a cycle, so its source has to be moved to a
+    /// temporary name before anything else in the sequence can run.
+    cycle: bool,
+}
+
+impl Sequence {
+    fn execute(&self, actions: &[Action], exec: &Execution) -> Vec<IndexedReport> {
+        let (results, parked) = self.run(actions, exec, false);
+        if let Some(tmp) = parked {
+            self.restore(actions, &tmp);
+        }
+        results
+    }
+
+    /// Runs the actions in order, skipping everything after the first failure or once
+    /// the execution is cancelled. If the sequence is a cycle that could not be
+    /// completed the temporary name its first file was parked at is returned, see
+    /// [`Sequence::restore`]. `keep_replaced` is passed on to [`Action::execute_from`].
+    fn run(
+        &self,
+        actions: &[Action],
+        exec: &Execution,
+        keep_replaced: bool,
+    ) -> (Vec<IndexedReport>, Option<PathBuf>) {
+        let mut results = Vec::with_capacity(self.order.len());
+        let mut blocked_by: Option<PathBuf> = None;
+        let mut parked: Option<PathBuf> = None;
+        let last = *self.order.last().expect("Sequences are never empty");
+
+        // Cycles are only checked before they start, stopping halfway would leave a
+        // file at its temporary name.
+        let mut cancelled = self.cycle && exec.is_cancelled();
+        if self.cycle && !cancelled {
+            let first = &actions[last];
+            let start = Instant::now();
+            match park(first.source()) {
+                Ok(tmp) => parked = Some(tmp),
+                Err(e) => {
+                    log::error!(
+                        "Could not move {:?} out of a rename cycle: {e}",
+                        first.source()
+                    );
+                    results.push((last, first.failed(e, start.elapsed())));
+                    exec.advance(first.source());
+                    blocked_by = Some(first.source().to_owned());
+                }
+            }
+        }
+
+        for (pos, idx) in self.order.iter().enumerate() {
+            let action = &actions[*idx];
+            let is_last = pos == self.order.len() - 1;
+            cancelled = cancelled || (!self.cycle && blocked_by.is_none() && exec.is_cancelled());
+            if cancelled {
+                results.push((*idx, action.cancelled()));
+                continue;
+            }
+            if let Some(blocker) = &blocked_by {
+                if is_last && self.cycle && parked.is_none() {
+                    continue;
+                }
+                let err = ActionError::DependencyFailed {
+                    blocked_by: blocker.clone(),
+                };
+                results.push((*idx, action.failed(err, Duration::ZERO)));
+                exec.advance(action.source());
+                continue;
+            }
+            let from = match (&parked, is_last) {
+                (Some(tmp), true) => tmp.as_path(),
+                _ => action.source(),
+            };
+            let rep = action.execute_from(from, keep_replaced);
+            if rep.is_failure() {
+                blocked_by = Some(action.source().to_owned());
+            }
+            results.push((*idx, rep));
+            exec.advance(action.source());
+        }
+
+        let stranded = parked.filter(|_| blocked_by.is_some());
+        (results, stranded)
+    }
+
+    /// Moves a file parked by [`Sequence::run`] back to its original name, if that
+    /// name is free again.
+    fn restore(&self, actions: &[Action], tmp: &Path) {
+        let first = &actions[*self.order.last().expect("Sequences are never empty")];
+        if noclobber::rename(tmp, first.source()).is_ok() {
+            log::warn!("Restored {:?} after a failed rename cycle", first.source());
+        } else {
+            log::error!(
+                "{:?} was left at {:?} after a failed rename cycle",
+                first.source(),
+                tmp
+            );
+        }
+    }
+}
+
+/// Orders the actions so that no rename lands on a file that is itself about to be
+/// renamed. Targets are unique once collisions are ruled out, so the dependencies form
+/// disjoint chains and cycles which are returned as independent sequences.
+fn schedule(actions: &[Action]) -> Vec<Sequence> {
+    let by_source: HashMap<PathBuf, usize> = actions
+        .iter()
+        .enumerate()
+        .rev()
+        .map(|(idx, act)| (normalize(act.source()), idx))
+        .collect();
+    let next: Vec<Option<usize>> = actions
+        .iter()
+        .enumerate()
+        .map(|(idx, act)| {
+            by_source
+                .get(&normalize(act.target()))
+                .copied()
+                .filter(|dep| *dep != idx)
+        })
+        .collect();
+    let mut has_dependent = vec![false; actions.len()];
+    for dep in next.iter().flatten() {
+        has_dependent[*dep] = true;
+    }
+
+    let mut visited = vec![false; actions.len()];
+    let mut sequences = Vec::new();
+    let walk = |start: usize, visited: &mut [bool]| {
+        let mut order = Vec::new();
+        let mut cur = Some(start);
+        while let Some(idx) = cur {
+            if visited[idx] {
+                break;
+            }
+            visited[idx] = true;
+            order.push(idx);
+            cur = next[idx];
+        }
+        order
+    };
+    for start in (0..actions.len()).filter(|idx| !has_dependent[*idx]) {
+        let mut order = walk(start, &mut visited);
+        order.reverse();
+        sequences.push(Sequence {
+            order,
+            cycle: false,
+        });
+    }
+    for start in 0..actions.len() {
+        if !visited[start] {
+            let mut order = walk(start, &mut visited);
+            order.reverse();
+            sequences.push(Sequence { order, cycle: true });
+        }
+    }
+    sequences
+}
+
+fn normalize(p: &Path) -> PathBuf {
+    std::path::absolute(p).unwrap_or_else(|_| p.to_owned())
+}
+
+/// Deletes a file an atomic batch replaced, once the batch can no longer be rolled back.
+fn remove_replaced(aside: &Path) {
+    let removed = match fs::symlink_metadata(aside) {
+        Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(aside),
+        Ok(_) => fs::remove_file(aside),
+        Err(e) => Err(e),
+    };
+    if let Err(e) = removed {
+        log::error!("Could not remove the replaced file left at {aside:?}!: {e}");
+    }
+}
+
+/// Moves `p` to an unused temporary name next to it.
+pub(crate) fn park(p: &Path) -> Result<PathBuf, ActionError> {
+    let name = p
+        .file_name()
+        .ok_or(ActionError::CannotRenameDotDot)?
+        .to_string_lossy();
+    loop {
+        let tmp = p.with_file_name(format!(".{name}.renamer-{:08x}", rand::random::<u32>()));
+        match noclobber::rename(p, &tmp) {
+            Ok(()) => return Ok(tmp),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// Several files in one batch that map to the same target name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Collision {
+    target: PathBuf,
+    sources: Vec<PathBuf>,
+}
+
+impl Collision {
+    pub fn target(&self) -> &Path {
+        &self.target
+    }
+
+    pub fn sources(&self) -> &[PathBuf] {
+        &self.sources
+    }
+
+    /// One error per colliding file.
+    pub fn errors(&self) -> impl Iterator<Item = RenameError> + '_ {
+        self.sources.iter().map(|source| {
+            RenameError::new(
+                source.clone(),
+                Some(self.target.clone()),
+                ActionError::Collision(self.clone()),
+            )
+        })
+    }
+}
+
+impl Display for Collision {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let sources = self
+            .sources
+            .iter()
+            .map(|x| format!("{}", x.display()))
+            .collect::<Vec<_>>()
+            .join(", ");
+        write!(
+            f,
+            "Multiple files would be renamed to {}: {sources}",
+            self.target.display()
+        )
+    }
+}
+
+/// An atomic batch that was undone because one of its renames failed.
+#[derive(Debug)]
+pub struct Rollback {
+    failed: Action,
+    cause: Box<ActionError>,
+    reverted: Vec<(PathBuf, PathBuf)>,
+    not_reverted: Vec<(PathBuf, io::Error)>,
+}
+
+impl Rollback {
+    /// Reverts `done` newest first, so that files renamed in a chain are moved back
+    /// in the opposite order to the one they were renamed in.
+    fn revert(
+        failed: Action,
+        cause: ActionError,
+        done: impl DoubleEndedIterator<Item = Report>,
+    ) -> Self {
+        let mut rollback = Self {
+            failed,
+            cause: Box::new(cause),
+            reverted: Vec::new(),
+            not_reverted: Vec::new(),
+        };
+        for report in done.rev() {
+            let (from, to, displaced) = match report {
+                Report::Renamed {
+                    from,
+                    to,
+                    displaced,
+                    ..
+                } => (from, to, displaced),
+                Report::Overwrote {
+                    from, to, replaced, ..
+                } => {
+                    if replaced.is_none() {
+                        log::warn!(
+                            "{to:?} replaced an existing file, that file cannot be restored"
+                        );
+                        let lost = io::Error::other("the file it replaced cannot be restored");
+                        rollback.not_reverted.push((to.clone(), lost));
+                    }
+                    (from, to, replaced)
+                }
+                _ => continue,
+            };
+            match moves::move_path(&to, &from, false) {
+                Ok(_) => {
+                    if let Some(aside) = displaced {
+                        conflict::restore_displaced(&aside, &to);
+                    }
+                    rollback.reverted.push((to, from));
+                }
+                Err(e) => {
+                    log::error!("Could not roll back {to:?} to {from:?}: {e}");
+                    rollback.not_reverted.push((to, e));
+                }
+            }
+        }
+        rollback
+    }
+
+    /// The action whose failure caused the rollback.
+    pub fn failed(&self) -> &Action {
+        &self.failed
+    }
+
+    pub fn cause(&self) -> &ActionError {
+        &self.cause
+    }
+
+    /// Renamed files that were moved back, as (renamed path, original path) pairs.
+    pub fn reverted(&self) -> &[(PathBuf, PathBuf)] {
+        &self.reverted
+    }
+
+    /// Renamed files that could not be moved back, with the reason why.
+    pub fn not_reverted(&self) -> &[(PathBuf, io::Error)] {
+        &self.not_reverted
+    }
+}
+
+impl Display for Rollback {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}. Rolled back {} renames",
+            self.cause,
+            self.reverted.len()
+        )?;
+        if !self.not_reverted.is_empty() {
+            let stuck = self
+                .not_reverted
+                .iter()
+                .map(|(path, e)| format!("{} ({e})", path.display()))
+                .collect::<Vec<_>>()
+                .join(", ");
+            write!(f, ", could not roll back: {stuck}")?;
+        }
+        Ok(())
+    }
+}
+
+impl IntoIterator for RenamePlan {
+    type Item = Action;
+    type IntoIter = std::vec::IntoIter<Action>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.actions.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a RenamePlan {
+    type Item = &'a Action;
+    type IntoIter = std::slice::Iter<'a, Action>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.actions.iter()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        fs,
+        path::Path,
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    use super::{RenamePlan, Sequence, schedule};
+    use crate::{
+        Action, ActionGroup, ConflictPolicy, RenamePattern,
+        error::{ActionError, ErrorCategory},
+        patterns::ActionOptions,
+        progress::{CancellationToken, Progress},
+        report::{Report, SkipReason},
+    };
+
+    fn plan_of(dir: &Path, renames: &[(&str, &str)]) -> RenamePlan {
+        plan_with(dir, renames, ConflictPolicy::Fail)
+    }
+
+    fn plan_with(dir: &Path, renames: &[(&str, &str)], on_conflict: ConflictPolicy) -> RenamePlan {
+        RenamePlan::new(
+            renames
+                .iter()
+                .map(|(old, new)| {
+                    Ok(Action {
+                        old: dir.join(old),
+                        new: dir.join(new),
+                        on_conflict: on_conflict.clone(),
+                        planned_in: Duration::ZERO,
+                    })
+                })
+                .collect(),
+        )
+    }
+
+    fn write_files(dir: &Path, names: &[&str]) {
+        for name in names {
+            fs::write(dir.join(name), name).unwrap();
+        }
+    }
+
+    #[test]
+    fn plan_does_not_touch_disk() {
+        let dir = tempfile::tempdir().unwrap();
+        let files = ["a.txt", "b.txt"].map(|f| dir.path().join(f));
+        let mut group = ActionGroup::new(0);
+        for file in files.iter() {
+            fs::File::create(file).unwrap();
+            group.add_file(file.clone());
+        }
+        group.add_pattern(
+            RenamePattern::parse("renamed_/ORIGINAL/", ActionOptions::default()).unwrap(),
+        );
+
+        let mut plan = group.plan();
+        assert_eq!(plan.len(), 2);
+        for action in plan.actions() {
+            assert!(action.source().exists());
+            assert!(!action.target().exists());
+        }
+
+        plan.retain(|act| act.source().ends_with("a.txt"));
+        let results = plan.execute();
+        assert_eq!(results.len(), 1);
+        assert!(matches!(results[0], Report::Renamed { .. }));
+        assert!(dir.path().join("renamed_a.txt").exists());
+        assert!(dir.path().join("b.txt").exists());
+    }
+
+    #[test]
+    fn collisions_block_execution() {
+        let dir = tempfile::tempdir().unwrap();
+        let files = ["a.txt", "b.txt", "c.txt"].map(|f| dir.path().join(f));
+        let mut group = ActionGroup::new(0);
+        for file in files.iter() {
+            fs::File::create(file).unwrap();
+            group.add_file(file.clone());
+        }
+        group.add_pattern(RenamePattern::parse("same", ActionOptions::default()).unwrap());
+
+        let plan = group.plan();
+        let collisions = plan.collisions();
+        assert_eq!(collisions.len(), 1);
+        assert_eq!(collisions[0].sources().len(), 3);
+        assert!(collisions[0].target().ends_with("same"));
+
+        let results = plan.execute();
+        assert_eq!(results.len(), 3);
+        for res in results.iter() {
+            let err = res.error().unwrap();
+            assert!(files.iter().any(|f| f == err.path()));
+            assert_eq!(err.category(), ErrorCategory::TargetExists);
+        }
+        for file in files.iter() {
+            assert!(file.exists());
+        }
+    }
+
+    #[test]
+    fn preview_reports_every_file() {
+        let dir = tempfile::tempdir().unwrap();
+        write_files(dir.path(), &["a", "b", "c", "x"]);
+        let plan = plan_of(
+            dir.path(),
+            &[("a", "new_a"), ("b", "same"), ("c", "same"), ("x", "x")],
+        );
+        let reports = plan.preview();
+        assert_eq!(reports.len(), 4);
+        assert!(matches!(&reports[0], Report::Planned { to, .. } if to.ends_with("new_a")));
+        for rep in &reports[1..3] {
+            assert_eq!(rep.error().unwrap().category(), ErrorCategory::TargetExists);
+        }
+        assert!(matches!(reports[3], Report::Unchanged { .. }));
+        assert!(dir.path().join("a").exists());
+    }
+
+    #[test]
+    fn schedule_orders_chains_and_cycles() {
+        let dir = Path::new("/dir");
+        let plan = plan_of(
+            dir,
+            &[("1", "2"), ("2", "3"), ("a", "b"), ("b", "a"), ("x", "x")],
+        );
+        let sequences = schedule(plan.actions());
+        assert_eq!(
+            sequences,
+            vec![
+                Sequence {
+                    order: vec![1, 0],
+                    cycle: false
+                },
+                Sequence {
+                    order: vec![4],
+                    cycle: false
+                },
+                Sequence {
+                    order: vec![3, 2],
+                    cycle: true
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn swap_two_files() {
+        let dir = tempfile::tempdir().unwrap();
+        write_files(dir.path(), &["a", "b"]);
+        let results = plan_of(dir.path(), &[("a", "b"), ("b", "a")]).execute();
+        assert!(results.iter().all(|r| !r.is_failure()));
+        assert_eq!(fs::read_to_string(dir.path().join("a")).unwrap(), "b");
+        assert_eq!(fs::read_to_string(dir.path().join("b")).unwrap(), "a");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn shift_numbered_series() {
+        let dir = tempfile::tempdir().unwrap();
+        write_files(dir.path(), &["1", "2", "3", "4"]);
+        let results = plan_of(
+            dir.path(),
+            &[("1", "2"), ("2", "3"), ("3", "4"), ("4", "5")],
+        )
+        .execute();
+        assert!(results.iter().all(|r| !r.is_failure()));
+        assert!(!dir.path().join("1").exists());
+        for n in 2..=5 {
+            let content = fs::read_to_string(dir.path().join(n.to_string())).unwrap();
+            assert_eq!(content, (n - 1).to_string());
+        }
+    }
+
+    #[test]
+    fn rotate_cycle() {
+        let dir = tempfile::tempdir().unwrap();
+        write_files(dir.path(), &["1", "2", "3"]);
+        let results = plan_of(dir.path(), &[("1", "2"), ("2", "3"), ("3", "1")]).execute();
+        assert!(results.iter().all(|r| !r.is_failure()));
+        assert_eq!(fs::read_to_string(dir.path().join("1")).unwrap(), "3");
+        assert_eq!(fs::read_to_string(dir.path().join("2")).unwrap(), "1");
+        assert_eq!(fs::read_to_string(dir.path().join("3")).unwrap(), "2");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 3);
+    }
+
+    #[test]
+    fn cancel_stops_new_renames() {
+        let dir = tempfile::tempdir().unwrap();
+        write_files(dir.path(), &["1", "2", "3", "4"]);
+        let renames = [("1", "2"), ("2", "3"), ("3", "4"), ("4", "5")];
+        let cancel = CancellationToken::new();
+        let seen = AtomicUsize::new(0);
+        let observer = |progress: Progress<'_>| {
+            assert_eq!(progress.total, 4);
+            seen.fetch_add(1, Ordering::Relaxed);
+            cancel.cancel();
+        };
+        let results = plan_of(dir.path(), &renames).execute_with(&observer, &cancel);
+        assert_eq!(seen.load(Ordering::Relaxed), 1);
+        assert!(matches!(results[3], Report::Renamed { .. }));
+        for rep in &results[..3] {
+            assert!(matches!(
+                rep,
+                Report::Skipped {
+                    reason: SkipReason::Cancelled,
+                    ..
+                }
+            ));
+        }
+        assert!(dir.path().join("5").exists());
+        assert!(!dir.path().join("4").exists());
+
+        fs::rename(dir.path().join("5"), dir.path().join("4")).unwrap();
+        let cancel = CancellationToken::new();
+        let observer = |_: Progress<'_>| cancel.cancel();
+        let results = plan_of(dir.path(), &renames).execute_atomic_with(&observer, &cancel);
+        assert_eq!(results.len(), 1);
+        let ActionError::RolledBack(rollback) = results[0].error().unwrap().cause() else {
+            panic!("Expected a rollback");
+        };
+        assert!(matches!(rollback.cause(), ActionError::Cancelled));
+        assert_eq!(rollback.reverted().len(), 1);
+        for name in ["1", "2", "3", "4"] {
+            let content = fs::read_to_string(dir.path().join(name)).unwrap();
+            assert_eq!(content, name);
+        }
+    }
+
+    #[test]
+    fn atomic_rolls_back_on_failure() {
+        let dir = tempfile::tempdir().unwrap();
+        write_files(dir.path(), &["1", "2", "x"]);
+        let results =
+            plan_of(dir.path(), &[("1", "2"), ("2", "3"), ("x", "missing/x")]).execute_atomic();
+        assert_eq!(results.len(), 1);
+        let Some(err) = results[0].error() else {
+            panic!("Expected the batch to be rolled back");
+        };
+        let ActionError::RolledBack(rollback) = err.cause() else {
+            panic!("Expected a rollback, got {err}");
+        };
+        assert!(rollback.failed().source().ends_with("x"));
+        assert_eq!(rollback.reverted().len(), 2);
+        assert!(rollback.not_reverted().is_empty());
+        for name in ["1", "2", "x"] {
+            let content = fs::read_to_string(dir.path().join(name)).unwrap();
+            assert_eq!(content, name);
+        }
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 3);
+    }
+
+    #[test]
+    fn atomic_rollback_restores_overwritten_files() {
+        let dir = tempfile::tempdir().unwrap();
+        write_files(dir.path(), &["x", "keep", "y"]);
+        let renames = [("x", "keep"), ("y", "missing/y")];
+        let results = plan_with(dir.path(), &renames, ConflictPolicy::Overwrite).execute_atomic();
+        assert_eq!(results.len(), 1);
+        let ActionError::RolledBack(rollback) = results[0].error().unwrap().cause() else {
+            panic!("Expected a rollback");
+        };
+        assert_eq!(rollback.reverted().len(), 1);
+        assert!(rollback.not_reverted().is_empty());
+        for name in ["x", "keep", "y"] {
+            let content = fs::read_to_string(dir.path().join(name)).unwrap();
+            assert_eq!(content, name);
+        }
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 3);
+
+        // Once the batch succeeds the replaced file is gone for good.
+        let results =
+            plan_with(dir.path(), &[("x", "keep")], ConflictPolicy::Overwrite).execute_atomic();
+        assert!(matches!(
+            results[..],
+            [Report::Overwrote { replaced: None, .. }]
+        ));
+        assert_eq!(fs::read_to_string(dir.path().join("keep")).unwrap(), "x");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn swap_through_symlinked_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let real = dir.path().join("real");
+        fs::create_dir(&real).unwrap();
+        fs::write(real.join("a"), "A").unwrap();
+        fs::write(real.join("b"), "B").unwrap();
+        fs::write(real.join("unchanged"), "U").unwrap();
+        let link = dir.path().join("link");
+        std::os::unix::fs::symlink(&real, &link).unwrap();
+
+        let mut group = ActionGroup::new(0);
+        for name in ["a", "b", "unchanged"] {
+            group.add_file(link.join(name));
+        }
+        let options = ActionOptions::new(true, ConflictPolicy::Overwrite);
+        for expr in ["s/^a$/c/", "s/^b$/a/", "s/^c$/b/"] {
+            group.add_pattern(RenamePattern::replace(expr, options.clone()).unwrap());
+        }
+        let results = group.execute();
+        assert!(
+            results
+                .iter()
+                .all(|r| matches!(r, Report::Renamed { .. } | Report::Unchanged { .. })),
+            "{results:?}"
+        );
+        assert_eq!(fs::read_to_string(real.join("a")).unwrap(), "B");
+        assert_eq!(fs::read_to_string(real.join("b")).unwrap(), "A");
+        assert_eq!(fs::read_to_string(real.join("unchanged")).unwrap(), "U");
+        assert_eq!(fs::read_dir(&real).unwrap().count(), 3);
+    }
+}
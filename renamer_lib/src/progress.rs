@@ -0,0 +1,84 @@
+use std::{
+    path::Path,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+};
+
+/// How far through a plan execution is.
+#[derive(Debug, Clone, Copy)]
+pub struct Progress<'a> {
+    /// Files that have been dealt with so far, including the current one.
+    pub done: usize,
+    pub total: usize,
+    /// The file that was just dealt with.
+    pub current: &'a Path,
+}
+
+/// Notified after each file in a plan is dealt with. Renames run in parallel, so it is
+/// called from several threads and calls may arrive out of order.
+pub trait ProgressObserver: Sync {
+    fn on_progress(&self, progress: Progress<'_>);
+}
+
+impl<F: Fn(Progress<'_>) + Sync> ProgressObserver for F {
+    fn on_progress(&self, progress: Progress<'_>) {
+        self(progress)
+    }
+}
+
+/// Shared flag that stops a running plan from starting any more renames. Renames that
+/// are already underway are finished first, so nothing is left half done.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// The observer and token for one execution, along with the shared count of files done.
+pub(crate) struct Execution<'a> {
+    observer: &'a dyn ProgressObserver,
+    cancel: &'a CancellationToken,
+    done: AtomicUsize,
+    total: usize,
+}
+
+impl<'a> Execution<'a> {
+    pub(crate) fn new(
+        observer: &'a dyn ProgressObserver,
+        cancel: &'a CancellationToken,
+        total: usize,
+    ) -> Self {
+        Self {
+            observer,
+            cancel,
+            done: AtomicUsize::new(0),
+            total,
+        }
+    }
+
+    pub(crate) fn is_cancelled(&self) -> bool {
+        self.cancel.is_cancelled()
+    }
+
+    pub(crate) fn advance(&self, current: &Path) {
+        let done = self.done.fetch_add(1, Ordering::Relaxed) + 1;
+        self.observer.on_progress(Progress {
+            done,
+            total: self.total,
+            current,
+        });
+    }
+}
@@ -1,41 +1,289 @@
-use std::fmt::Debug;
-use std::path::PathBuf;
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
+use serde::Serialize;
+
+use crate::error::RenameError;
+
+/// What happened to a single file. Every variant carries how long it took, for a rename
+/// that is the time spent on disk, for a planned rename the time taken to compute its
+/// new name.
+#[derive(Debug)]
 pub enum Report {
     Renamed {
         from: PathBuf,
         to: PathBuf,
-        overwrote: bool,
+        /// Where the file previously at `to` was moved, under [`crate::ConflictPolicy::KeepBoth`].
+        displaced: Option<PathBuf>,
+        /// The target was on another filesystem, so the file was copied there and the
+        /// original removed.
+        copied: bool,
+        duration: Duration,
+    },
+    /// Renamed, replacing the file that was already at `to`.
+    Overwrote {
+        from: PathBuf,
+        to: PathBuf,
+        /// Where the replaced file is kept while an atomic batch runs, so it can be put back
+        /// if the batch is rolled back. It is deleted, and this is `None`, once the batch
+        /// has succeeded.
+        replaced: Option<PathBuf>,
+        copied: bool,
+        duration: Duration,
     },
-    Nothing,
+    Skipped {
+        path: PathBuf,
+        target: PathBuf,
+        reason: SkipReason,
+        duration: Duration,
+    },
+    Failed {
+        error: RenameError,
+        duration: Duration,
+    },
+    /// A rename that would be performed, from a plan that was not executed.
+    Planned {
+        from: PathBuf,
+        to: PathBuf,
+        duration: Duration,
+    },
+    /// The new name is the same as the old one, so there was nothing to do.
+    Unchanged { path: PathBuf, duration: Duration },
+}
+
+/// Why a file was left alone without it being an error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkipReason {
+    /// The target already existed and the conflict policy said to skip the file.
+    TargetExists,
+    /// The execution was cancelled before the file was renamed.
+    Cancelled,
 }
 
-impl Debug for Report {
+impl Display for SkipReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SkipReason::TargetExists => write!(f, "target already exists"),
+            SkipReason::Cancelled => write!(f, "cancelled"),
+        }
+    }
+}
+
+impl Report {
+    /// The file the report is about, as it was named before the batch.
+    pub fn path(&self) -> &Path {
+        match self {
+            Report::Renamed { from, .. }
+            | Report::Overwrote { from, .. }
+            | Report::Planned { from, .. } => from,
+            Report::Skipped { path, .. } | Report::Unchanged { path, .. } => path,
+            Report::Failed { error, .. } => error.path(),
+        }
+    }
+
+    pub fn duration(&self) -> Duration {
+        match self {
+            Report::Renamed { duration, .. }
+            | Report::Overwrote { duration, .. }
+            | Report::Skipped { duration, .. }
+            | Report::Failed { duration, .. }
+            | Report::Planned { duration, .. }
+            | Report::Unchanged { duration, .. } => *duration,
+        }
+    }
+
+    pub fn is_failure(&self) -> bool {
+        matches!(self, Report::Failed { .. })
+    }
+
+    pub fn error(&self) -> Option<&RenameError> {
+        match self {
+            Report::Failed { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+
+    pub fn into_error(self) -> Option<RenameError> {
+        match self {
+            Report::Failed { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+
+    /// The report flattened to the fields scripts consume, this is also how reports are
+    /// serialized.
+    pub fn record(&self) -> Record<'_> {
+        let (from, to) = match self {
+            Report::Renamed { from, to, .. }
+            | Report::Overwrote { from, to, .. }
+            | Report::Planned { from, to, .. } => (from.as_path(), Some(to.as_path())),
+            Report::Skipped { path, target, .. } => (path.as_path(), Some(target.as_path())),
+            Report::Unchanged { path, .. } => (path.as_path(), Some(path.as_path())),
+            Report::Failed { error, .. } => (error.path(), error.target()),
+        };
+        Record {
+            from,
+            to,
+            status: self.status(),
+            error: match self {
+                Report::Failed { error, .. } => Some(error.cause().to_string()),
+                Report::Skipped { reason, .. } => Some(reason.to_string()),
+                _ => None,
+            },
+            overwrote: matches!(self, Report::Overwrote { .. }),
+        }
+    }
+
+    pub fn status(&self) -> Status {
+        match self {
+            Report::Renamed { .. } => Status::Renamed,
+            Report::Overwrote { .. } => Status::Overwrote,
+            Report::Skipped { .. } => Status::Skipped,
+            Report::Failed { .. } => Status::Failed,
+            Report::Planned { .. } => Status::Planned,
+            Report::Unchanged { .. } => Status::Unchanged,
+        }
+    }
+
+    /// The file was moved from `from` to `to` on disk.
+    pub fn moved(&self) -> Option<(&Path, &Path)> {
+        match self {
+            Report::Renamed { from, to, .. } | Report::Overwrote { from, to, .. } => {
+                Some((from, to))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Display for Report {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Report::Renamed {
                 from,
                 to,
-                overwrote,
+                displaced,
+                copied,
+                ..
             } => {
-                let mut out = format!("Renamed {:?} to {}", from, format_report_path(to));
-                if *overwrote {
-                    out.push_str("(OVERWROTE)");
+                write!(f, "Renamed {} to {}", from.display(), to.display())?;
+                if let Some(displaced) = displaced {
+                    write!(f, ", moved the existing file to {}", displaced.display())?;
                 }
-                write!(f, "{out}")
+                if *copied {
+                    write!(f, " (copied across filesystems)")?;
+                }
+                Ok(())
+            }
+            Report::Overwrote {
+                from, to, copied, ..
+            } => {
+                write!(
+                    f,
+                    "Renamed {} to {}, replacing the existing {}",
+                    from.display(),
+                    to.display(),
+                    to.display()
+                )?;
+                if *copied {
+                    write!(f, " (copied across filesystems)")?;
+                }
+                Ok(())
             }
-            Report::Nothing => Ok(()),
+            Report::Skipped {
+                path,
+                target,
+                reason,
+                ..
+            } => write!(
+                f,
+                "Skipped {} -> {}: {reason}",
+                path.display(),
+                target.display()
+            ),
+            Report::Failed { error, .. } => write!(f, "Failed {error}"),
+            Report::Planned { from, to, .. } => {
+                write!(f, "Will rename {} to {}", from.display(), to.display())
+            }
+            Report::Unchanged { path, .. } => write!(f, "{} is unchanged", path.display()),
         }
     }
 }
 
-fn format_report_path<'a>(p: &PathBuf) -> String {
-    p.canonicalize()
-        .unwrap_or_else(|x| {
-            let err_str = format!("Error: {x} in report path!");
-            log::error!("{err_str}");
-            err_str.into()
-        })
-        .to_string_lossy()
-        .to_string()
+impl Serialize for Report {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.record().serialize(serializer)
+    }
+}
+
+/// The outcome of a [`Report`] without its details.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Renamed,
+    Overwrote,
+    Skipped,
+    Failed,
+    Planned,
+    Unchanged,
+}
+
+/// A [`Report`] as one flat row. The field names and status strings are kept stable so
+/// that scripts can rely on them.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Record<'a> {
+    pub from: &'a Path,
+    /// Missing if the file failed before its new name was known.
+    pub to: Option<&'a Path>,
+    pub status: Status,
+    /// Why the file failed or was skipped.
+    pub error: Option<String>,
+    pub overwrote: bool,
+}
+
+#[cfg(test)]
+mod test {
+    use std::{path::PathBuf, time::Duration};
+
+    use super::Report;
+    use crate::error::{ActionError, RenameError};
+
+    #[test]
+    fn serializes_as_flat_record() {
+        let overwrote = Report::Overwrote {
+            from: PathBuf::from("/dir/a"),
+            to: PathBuf::from("/dir/b"),
+            replaced: None,
+            copied: false,
+            duration: Duration::from_millis(3),
+        };
+        assert_eq!(
+            serde_json::to_value(&overwrote).unwrap(),
+            serde_json::json!({
+                "from": "/dir/a",
+                "to": "/dir/b",
+                "status": "overwrote",
+                "error": null,
+                "overwrote": true,
+            })
+        );
+
+        let failed = Report::Failed {
+            error: RenameError::new(PathBuf::from("/dir/c"), None, ActionError::TargetExists),
+            duration: Duration::ZERO,
+        };
+        assert_eq!(
+            serde_json::to_value(&failed).unwrap(),
+            serde_json::json!({
+                "from": "/dir/c",
+                "to": null,
+                "status": "failed",
+                "error": "Target already exists",
+                "overwrote": false,
+            })
+        );
+    }
 }